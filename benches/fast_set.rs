@@ -0,0 +1,75 @@
+//! Rough timings for choosing between algorithms. Run with `cargo bench`.
+
+use fast_set::FastSet;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// Runs `f` repeatedly for about 100ms and returns the mean time per call.
+fn time<T>(mut f: impl FnMut() -> T) -> Duration {
+    let start = Instant::now();
+    let mut runs = 0;
+    while start.elapsed() < Duration::from_millis(100) {
+        black_box(f());
+        runs += 1;
+    }
+    start.elapsed() / runs
+}
+
+/// Returns a set over `0..cap` holding every `step`th key, scattered so
+/// that `keys()` isn't in ascending order.
+fn strided(cap: usize, step: usize) -> FastSet {
+    let mut set = FastSet::new(cap).unwrap();
+    let count = cap / step;
+    for i in 0..count {
+        set.add((i * 7919 % count) * step).unwrap();
+    }
+    set
+}
+
+fn subset() {
+    println!("is_subset: cap, step, probing, dense");
+    for cap in [1 << 16, 1 << 19, 1 << 20] {
+        let all = strided(cap, 1);
+        for step in [1, 2, 3, 4, 8] {
+            let set = strided(cap, step);
+            let probing = time(|| set.keys().iter().all(|&key| all.contains(key)));
+            let dense = time(|| set.is_subset_dense(&all));
+            println!("{cap}, {step}, {probing:?}, {dense:?}");
+        }
+    }
+}
+
+fn from_sorted() {
+    println!("from_sorted: len, from_sorted_slice, from_sorted_trusted");
+    let cap = 1 << 20;
+    for step in [1, 16] {
+        let keys = strided(cap, step).to_sorted_vec();
+        let checked = time(|| FastSet::from_sorted_slice(cap, &keys).unwrap());
+        let trusted = time(|| unsafe { FastSet::from_sorted_trusted(cap, &keys) });
+        println!("{}, {checked:?}, {trusted:?}", keys.len());
+    }
+}
+
+fn merge_sorted() {
+    println!("merge_sorted_run: len, add_all_reporting, merge_sorted_run");
+    let cap = 1 << 20;
+    for step in [1, 16] {
+        let keys = strided(cap, step).to_sorted_vec();
+        let mut set = FastSet::new(cap).unwrap();
+        let add_all = time(|| {
+            set.clear();
+            set.add_all_reporting(keys.iter().copied()).unwrap()
+        });
+        let merged = time(|| {
+            set.clear();
+            set.merge_sorted_run(&keys).unwrap()
+        });
+        println!("{}, {add_all:?}, {merged:?}", keys.len());
+    }
+}
+
+fn main() {
+    subset();
+    from_sorted();
+    merge_sorted();
+}
@@ -1,6 +1,12 @@
-use std::alloc::{alloc, alloc_zeroed, dealloc, realloc, Layout, LayoutError};
+#[cfg(feature = "rand")]
+use rand::Rng;
+use std::alloc::{alloc, alloc_zeroed, dealloc, handle_alloc_error, realloc, Layout, LayoutError};
+use std::collections::HashMap;
 use std::error::Error;
-use std::fmt::Display;
+use std::fmt::{Debug, Display};
+use std::io::{self, Read};
+use std::ops::Range;
+use std::ptr::NonNull;
 
 #[derive(Debug)]
 /// An `OutOfBounds` error occurs when [`FastSet::add`] or [`FastSet::remove`]
@@ -21,6 +27,142 @@ impl Display for OutOfBounds {
 }
 impl Error for OutOfBounds {}
 
+#[derive(Debug)]
+/// A `FastSetError` is returned when creating a set with
+/// [`FastSet::new_with_limit`] fails.
+pub enum FastSetError {
+    /// The capacity is greater than `isize::MAX`, as for [`FastSet::new`].
+    Layout(LayoutError),
+    /// A set with capacity `cap` would need more than `max_bytes` bytes.
+    LimitExceeded { cap: usize, max_bytes: usize },
+}
+
+impl Display for FastSetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FastSetError::Layout(err) => Display::fmt(err, f),
+            FastSetError::LimitExceeded { cap, max_bytes } => write!(
+                f,
+                "memory limit exceeded: cap = {}, max_bytes = {}",
+                cap, max_bytes
+            ),
+        }
+    }
+}
+impl Error for FastSetError {}
+
+impl From<LayoutError> for FastSetError {
+    fn from(err: LayoutError) -> Self {
+        FastSetError::Layout(err)
+    }
+}
+
+#[derive(Debug)]
+/// A `DecodeError` is returned when [`FastSet::from_delta_varint`] is given
+/// bytes that aren't a valid encoding.
+pub enum DecodeError {
+    /// The input ended in the middle of the encoding.
+    UnexpectedEnd,
+    /// A varint, or a key computed from one, doesn't fit in a `usize`.
+    Overflow,
+    /// A key wasn't greater than the one before it.
+    NotIncreasing { key: usize },
+    /// A key is `>= cap`.
+    OutOfBounds(OutOfBounds),
+    /// The encoded capacity is greater than `isize::MAX`.
+    Layout(LayoutError),
+    /// There were bytes left over after the last key.
+    TrailingBytes,
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of input"),
+            DecodeError::Overflow => write!(f, "value doesn't fit in a usize"),
+            DecodeError::NotIncreasing { key } => write!(f, "keys not increasing: key = {}", key),
+            DecodeError::OutOfBounds(err) => Display::fmt(err, f),
+            DecodeError::Layout(err) => Display::fmt(err, f),
+            DecodeError::TrailingBytes => write!(f, "trailing bytes after last key"),
+        }
+    }
+}
+impl Error for DecodeError {}
+
+impl From<OutOfBounds> for DecodeError {
+    fn from(err: OutOfBounds) -> Self {
+        DecodeError::OutOfBounds(err)
+    }
+}
+
+impl From<LayoutError> for DecodeError {
+    fn from(err: LayoutError) -> Self {
+        DecodeError::Layout(err)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// An allocator call made by a set created with [`FastSet::with_alloc_hook`].
+/// Each of the set's two internal arrays is allocated, resized, and freed
+/// separately, so most operations report two events. Sizes are in bytes.
+pub enum AllocEvent {
+    /// A new array of `bytes` bytes was allocated.
+    Alloc { bytes: usize },
+    /// An array of `bytes` bytes was freed.
+    Dealloc { bytes: usize },
+    /// An array was resized from `old_bytes` to `new_bytes` bytes, possibly
+    /// in place.
+    Realloc { old_bytes: usize, new_bytes: usize },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// Which of two sets a key came from, as reported by
+/// [`FastSet::tagged_union`].
+pub enum Source {
+    /// The key is in both sets.
+    Both,
+    /// The key is only in `self`.
+    SelfOnly,
+    /// The key is only in `other`.
+    OtherOnly,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// A single mutation of a [`FastSet`], for persisting a log of operations
+/// and replaying it with [`FastSet::apply_commands`].
+pub enum SetCommand {
+    /// [`FastSet::add`] the key.
+    Add(usize),
+    /// [`FastSet::remove`] the key.
+    Remove(usize),
+    /// [`FastSet::clear`] the set.
+    Clear,
+    /// [`FastSet::toggle`] the key.
+    Toggle(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// How one set relates to another, as returned by [`FastSet::relate`]. When
+/// several apply, the first listed here wins: two empty sets are `Equal`,
+/// and an empty set is a `Subset` of any other set rather than `Disjoint`.
+pub enum Relation {
+    /// The sets have the same keys.
+    Equal,
+    /// Every key of `self` is in `other`, which has more keys.
+    Subset,
+    /// Every key of `other` is in `self`, which has more keys.
+    Superset,
+    /// The sets share no keys.
+    Disjoint,
+    /// The sets share some keys, and each has keys the other lacks.
+    Overlapping,
+}
+
+/// The maximum number of keys shown by `FastSet`'s [`Debug`] implementation.
+/// Larger sets show their `DEBUG_MAX_KEYS` smallest keys followed by the total
+/// length; use [`FastSet::to_sorted_vec`] to see everything.
+pub const DEBUG_MAX_KEYS: usize = 16;
+
 /// A `FastSet` is a set of `usize` with fast add, remove, contains, and clear operations.
 /// Each instance of `FastSet` has some maximal value, and uses heap space
 /// proportional to that value. Every operation except cloning, including
@@ -33,6 +175,15 @@ pub struct FastSet {
     backref: *mut usize,
     len: usize,
     cap: usize,
+    max_len_seen: usize,
+    max_key_seen: usize,
+    /// The keys in ascending order, if `sorted_valid`. Kept when the keys
+    /// change, so re-sorting can reuse the buffer.
+    sorted: Vec<usize>,
+    /// Whether `sorted` matches the keys, i.e. they haven't changed since.
+    sorted_valid: bool,
+    /// Called on every allocator call, if set by `with_alloc_hook`.
+    alloc_hook: Option<Box<dyn Fn(AllocEvent)>>,
 }
 
 impl FastSet {
@@ -40,15 +191,311 @@ impl FastSet {
     /// Allocates `O(cap)` bytes of heap memory.
     /// Returns an error if `cap` is greater than `isize::MAX`.
     pub fn new(cap: usize) -> Result<FastSet, LayoutError> {
-        let layout = Layout::array::<usize>(cap)?;
-        let sparse = unsafe { alloc_zeroed(layout) as *mut usize };
-        let backref = unsafe { alloc(layout) as *mut usize };
-        Ok(FastSet {
+        Self::new_hooked(cap, None)
+    }
+
+    /// Like [`new`](FastSet::new), but reporting the allocations to
+    /// `alloc_hook` and keeping it for later ones.
+    fn new_hooked(
+        cap: usize,
+        alloc_hook: Option<Box<dyn Fn(AllocEvent)>>,
+    ) -> Result<FastSet, LayoutError> {
+        Layout::array::<usize>(cap)?;
+        let (sparse, sparse_event) = alloc_array(cap, true);
+        let (backref, backref_event) = alloc_array(cap, false);
+        let ret = FastSet {
             sparse,
             backref,
             len: 0,
             cap,
-        })
+            max_len_seen: 0,
+            max_key_seen: 0,
+            sorted: Vec::new(),
+            sorted_valid: false,
+            alloc_hook,
+        };
+        ret.report([sparse_event, backref_event]);
+        Ok(ret)
+    }
+
+    /// Create a new `FastSet` like [`new`](FastSet::new) that calls `hook`
+    /// with an [`AllocEvent`] whenever it allocates, resizes, or frees its
+    /// arrays, including the initial allocation and the frees on drop.
+    /// Useful for tracing memory behavior without a global allocator shim.
+    /// The hook isn't cloned: a clone of this set reports nothing, though
+    /// [`clone_from`](Clone::clone_from) into it still does. Empty arrays
+    /// never touch the allocator, so they report nothing. Sets without a
+    /// hook pay only a branch on each (already rare) allocator call.
+    ///
+    /// The hook runs once the set is consistent again, so it may panic; the
+    /// set is left with its new capacity. Frees are not reported when the set
+    /// is dropped during a panic, as a second panic would abort.
+    /// Returns an error if `cap` is greater than `isize::MAX`.
+    pub fn with_alloc_hook<H: Fn(AllocEvent) + 'static>(
+        cap: usize,
+        hook: H,
+    ) -> Result<FastSet, LayoutError> {
+        Self::new_hooked(cap, Some(Box::new(hook)))
+    }
+
+    /// Reports each of `events` to the allocation hook, if any. Only call
+    /// this once the set is consistent, since the hook may panic.
+    fn report<const N: usize>(&self, events: [Option<AllocEvent>; N]) {
+        if let Some(hook) = &self.alloc_hook {
+            events.into_iter().flatten().for_each(hook);
+        }
+    }
+
+    /// Create a new empty [`RecordingSet`] with capacity `cap`, which logs
+    /// every change to its keys as a [`SetCommand`]; see
+    /// [`RecordingSet::new`]. Plain `FastSet`s don't record anything.
+    /// Returns an error if `cap` is greater than `isize::MAX`.
+    pub fn with_recorder(cap: usize) -> Result<RecordingSet, LayoutError> {
+        RecordingSet::new(cap)
+    }
+
+    /// Create a new `FastSet` that can hold every key up to and including
+    /// `max_key`, i.e. with capacity `max_key + 1`. Returns an error if that
+    /// is greater than `isize::MAX`, including when `max_key == usize::MAX`.
+    pub fn new_for_max(max_key: usize) -> Result<FastSet, LayoutError> {
+        // usize::MAX is far too big anyway, so saturating keeps the error
+        Self::new(max_key.saturating_add(1))
+    }
+
+    /// Create a new `FastSet` like [`new`](FastSet::new), but return a
+    /// `LimitExceeded` error without allocating anything if the set would need
+    /// more than `max_bytes` bytes of heap memory, i.e. if
+    /// `2 * cap * size_of::<usize>() > max_bytes`.
+    pub fn new_with_limit(cap: usize, max_bytes: usize) -> Result<FastSet, FastSetError> {
+        let fits = cap
+            .checked_mul(2 * std::mem::size_of::<usize>())
+            .is_some_and(|bytes| bytes <= max_bytes);
+        if !fits {
+            return Err(FastSetError::LimitExceeded { cap, max_bytes });
+        }
+        Ok(Self::new(cap)?)
+    }
+
+    /// Create a new `FastSet` with capacity `cap` containing exactly `keys`,
+    /// which must be sorted and distinct. Runs in `O(keys.len())` time, with
+    /// no membership probes: `keys` is copied straight into the dense array,
+    /// comparing each key only with the one before it. Returns an
+    /// `OutOfBounds` at the first key that is `>= cap` or isn't greater than
+    /// the key before it.
+    ///
+    /// # Panics
+    /// Panics if `cap` is greater than `isize::MAX`.
+    pub fn from_sorted_slice(cap: usize, keys: &[usize]) -> Result<FastSet, OutOfBounds> {
+        let mut ret = Self::new(cap).unwrap();
+        for (i, &key) in keys.iter().enumerate() {
+            // Strictly increasing keys below cap also keep i below cap
+            if key >= cap || (i > 0 && keys[i - 1] >= key) {
+                return Err(OutOfBounds { cap, key });
+            }
+            unsafe {
+                *ret.sparse.add(key) = i;
+                *ret.backref.add(i) = key;
+            }
+            ret.max_key_seen = ret.max_key_seen.max(key);
+        }
+        ret.len = keys.len();
+        ret.max_len_seen = ret.len;
+        Ok(ret)
+    }
+
+    /// Create a new `FastSet` with capacity `cap` containing exactly
+    /// `sorted_keys`, like [`from_sorted_slice`](FastSet::from_sorted_slice)
+    /// but with no checks at all, for reloading trusted output of
+    /// [`to_sorted_vec`](FastSet::to_sorted_vec) as fast as possible.
+    ///
+    /// # Safety
+    /// `sorted_keys` must be sorted, distinct, and less than `cap`.
+    ///
+    /// # Panics
+    /// Panics if `cap` is greater than `isize::MAX`.
+    pub unsafe fn from_sorted_trusted(cap: usize, sorted_keys: &[usize]) -> FastSet {
+        let mut ret = Self::new(cap).unwrap();
+        std::ptr::copy_nonoverlapping(sorted_keys.as_ptr(), ret.backref, sorted_keys.len());
+        for (i, &key) in sorted_keys.iter().enumerate() {
+            *ret.sparse.add(key) = i;
+        }
+        ret.len = sorted_keys.len();
+        ret.max_len_seen = ret.len;
+        ret.max_key_seen = sorted_keys.last().copied().unwrap_or(0);
+        ret
+    }
+
+    /// Create a new `FastSet` with capacity `mask.len()`, containing every
+    /// index `i` for which `mask[i]` is `true`. Runs in `O(mask.len())` time.
+    /// Returns an error if `mask.len()` is greater than `isize::MAX`.
+    pub fn from_bool_slice(mask: &[bool]) -> Result<FastSet, LayoutError> {
+        let mut ret = Self::new(mask.len())?;
+        for (key, &present) in mask.iter().enumerate() {
+            if present {
+                unsafe { ret.unchecked_add(key) };
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Create a new `FastSet` with capacity `cap` from a packed bitmask,
+    /// streamed as `u64` words: bit `i` of the `w`th word means key
+    /// `w * 64 + i` is present. The words may stop short of `cap`, or go
+    /// past it as long as no bits past `cap` are set. Runs in time
+    /// proportional to the number of words plus the number of keys.
+    ///
+    /// Returns an `OutOfBounds` at the first set bit whose key is `>= cap`,
+    /// without reading any further words.
+    ///
+    /// # Panics
+    /// Panics if `cap` is greater than `isize::MAX`.
+    pub fn from_mask_iter<I: IntoIterator<Item = u64>>(
+        cap: usize,
+        words: I,
+    ) -> Result<FastSet, OutOfBounds> {
+        let mut ret = Self::new(cap).unwrap();
+        for (i, mut word) in words.into_iter().enumerate() {
+            let base = i.saturating_mul(64);
+            while word != 0 {
+                let key = base.saturating_add(word.trailing_zeros() as usize);
+                if key >= cap {
+                    return Err(OutOfBounds { cap, key });
+                }
+                unsafe { ret.unchecked_add(key) };
+                word &= word - 1;
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Create a new `FastSet` with capacity `scores.len()`, containing every
+    /// index `i` for which `scores[i] >= threshold`. NaN scores never pass.
+    /// Runs in `O(scores.len())` time. Returns an error if `scores.len()` is
+    /// greater than `isize::MAX`.
+    pub fn from_threshold(scores: &[f64], threshold: f64) -> Result<FastSet, LayoutError> {
+        Self::from_threshold_by(scores, |&score| score >= threshold)
+    }
+
+    /// Create a new `FastSet` with capacity `items.len()`, containing every
+    /// index `i` for which `pred(&items[i])` holds. Runs in `O(items.len())`
+    /// time. Returns an error if `items.len()` is greater than `isize::MAX`.
+    pub fn from_threshold_by<T, F: Fn(&T) -> bool>(
+        items: &[T],
+        pred: F,
+    ) -> Result<FastSet, LayoutError> {
+        let mut ret = Self::new(items.len())?;
+        for (key, item) in items.iter().enumerate() {
+            if pred(item) {
+                unsafe { ret.unchecked_add(key) };
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Create a new `FastSet` with capacity `scores.len()`, containing the
+    /// indices of the `k` largest scores (or all of them, if
+    /// `k > scores.len()`). Ties are broken in favor of lower indices, and
+    /// scores are compared with [`f64::total_cmp`], so a (positive) NaN
+    /// counts as larger than any number. Uses a partial selection rather
+    /// than a full sort, so it runs in `O(scores.len())` expected time.
+    /// Returns an error if `scores.len()` is greater than `isize::MAX`.
+    pub fn top_k_indices(scores: &[f64], k: usize) -> Result<FastSet, LayoutError> {
+        let mut ret = Self::new(scores.len())?;
+        let mut indices: Vec<usize> = (0..scores.len()).collect();
+        if k < indices.len() {
+            indices.select_nth_unstable_by(k, |&a, &b| {
+                scores[b].total_cmp(&scores[a]).then(a.cmp(&b))
+            });
+            indices.truncate(k);
+        }
+        for key in indices {
+            unsafe { ret.unchecked_add(key) };
+        }
+        Ok(ret)
+    }
+
+    /// Create a new `FastSet` with capacity `max(a.len(), b.len())`,
+    /// containing every index `i` where `a[i] != b[i]`. If the slices differ
+    /// in length, every index past the end of the shorter one counts as
+    /// changed. Runs in `O(max(a.len(), b.len()))` time. Returns an error if
+    /// the capacity is greater than `isize::MAX`.
+    pub fn diff_indices<T: PartialEq>(a: &[T], b: &[T]) -> Result<FastSet, LayoutError> {
+        let mut ret = Self::new(a.len().max(b.len()))?;
+        for key in 0..ret.cap {
+            if a.get(key) != b.get(key) {
+                unsafe { ret.unchecked_add(key) };
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Create a new `FastSet` with capacity `values.len()`, containing every
+    /// index `i` where `values[i]` is strictly greater than each of its
+    /// neighbors. The first and last values have only one neighbor to beat,
+    /// and a lone value has none, so it counts as a peak. Since the
+    /// comparison is strict, no value on a plateau of equal values is a
+    /// peak, and comparisons with NaN always fail. Runs in `O(values.len())` time.
+    /// Returns an error if `values.len()` is greater than `isize::MAX`.
+    pub fn local_maxima(values: &[f64]) -> Result<FastSet, LayoutError> {
+        let mut ret = Self::new(values.len())?;
+        for (key, &value) in values.iter().enumerate() {
+            let beats_left = key == 0 || value > values[key - 1];
+            let beats_right = key + 1 == values.len() || value > values[key + 1];
+            if beats_left && beats_right {
+                unsafe { ret.unchecked_add(key) };
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Create a new `FastSet` with capacity `cap` containing
+    /// `start, start + step, start + 2 * step, ...` up to but not including
+    /// `cap`. If `start >= cap` the set is empty. Runs in
+    /// `O(cap / step)` time.
+    ///
+    /// Returns an `OutOfBounds` for `start` if `step` is 0, since such a
+    /// pattern never advances.
+    ///
+    /// # Panics
+    /// Panics if `cap` is greater than `isize::MAX`.
+    pub fn from_stride(cap: usize, start: usize, step: usize) -> Result<FastSet, OutOfBounds> {
+        if step == 0 {
+            return Err(OutOfBounds { cap, key: start });
+        }
+        let mut ret = Self::new(cap).unwrap();
+        for key in (start..cap).step_by(step) {
+            unsafe { ret.unchecked_add(key) };
+        }
+        Ok(ret)
+    }
+
+    /// Create a new `FastSet` with capacity `cap` holding `k` distinct random
+    /// keys (or all of `0..cap`, if `k > cap`). Requires the `rand` feature.
+    ///
+    /// When `k <= cap / 2`, keys are drawn at random and redrawn if already
+    /// chosen, which takes `O(k)` expected time. Otherwise that could need
+    /// many redraws, so a partial Fisher-Yates shuffle of `0..cap` is used
+    /// instead, taking `O(cap)` time and memory.
+    ///
+    /// # Panics
+    /// Panics if `cap` is greater than `isize::MAX`.
+    #[cfg(feature = "rand")]
+    pub fn random_subset<R: Rng>(cap: usize, k: usize, rng: &mut R) -> FastSet {
+        let k = k.min(cap);
+        let mut ret = Self::new(cap).unwrap();
+        if k <= cap / 2 {
+            while ret.len < k {
+                // Checked, so a misbehaving Rng can't put a key out of bounds
+                ret.add(rng.random_range(0..cap)).unwrap();
+            }
+        } else {
+            let mut keys: Vec<usize> = (0..cap).collect();
+            for i in 0..k {
+                keys.swap(i, rng.random_range(i..cap));
+                unsafe { ret.unchecked_add(keys[i]) };
+            }
+        }
+        ret
     }
 
     /// Returns the length of the set, i.e. the number of items it contains.
@@ -56,6 +503,11 @@ impl FastSet {
         self.len
     }
 
+    /// Returns `true` if the set contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
     /// Returns the capacity of the set, i.e. the lowest value that cannot be
     /// stored. This is always equal to the value passed when calling
     /// [`new`](FastSet::new).
@@ -63,6 +515,31 @@ impl FastSet {
         self.cap
     }
 
+    /// Returns the number of elements allocated for the `sparse` array, which
+    /// maps keys to positions. For the current implementation this is always
+    /// equal to [`cap`](FastSet::cap).
+    pub fn sparse_capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// Returns the number of elements allocated for the `backref` array, which
+    /// holds the keys themselves. For the current implementation this is
+    /// always equal to [`cap`](FastSet::cap).
+    pub fn backref_capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// Returns the sizes in bytes of three ways to represent the current keys:
+    /// `(sparse, bitset, sorted)`, where `sparse = 2 * cap * size_of::<usize>()`
+    /// is this two-array form, `bitset = ceil(cap / 8)` is one bit per
+    /// possible key, and `sorted = len * size_of::<usize>()` is a sorted list
+    /// of the keys. The smallest is the most compact format for persisting
+    /// the set.
+    pub fn compare_repr_bytes(&self) -> (usize, usize, usize) {
+        let word = std::mem::size_of::<usize>();
+        (2 * self.cap * word, self.cap.div_ceil(8), self.len * word)
+    }
+
     /// Checks whether the set contains the given key. Will always return
     /// `false` if `key >= self.cap()`.
     pub fn contains(&self, key: usize) -> bool {
@@ -73,6 +550,43 @@ impl FastSet {
         }
     }
 
+    /// Checks whether [`add`](FastSet::add) would change the set, i.e. `key`
+    /// is in range and not yet present. Out-of-range keys give `false`,
+    /// since `add` would fail rather than insert them.
+    pub fn would_insert(&self, key: usize) -> bool {
+        key < self.cap && unsafe { !self.unchecked_contains(key) }
+    }
+
+    /// Returns what [`len`](FastSet::len) would be after
+    /// [`add`](FastSet::add)ing `key`, without changing the set: one more if
+    /// `key` would be inserted, and the same if it's already present or out
+    /// of range (where `add` would fail and so change nothing).
+    pub fn len_after_add(&self, key: usize) -> usize {
+        self.len + self.would_insert(key) as usize
+    }
+
+    /// Returns what [`len`](FastSet::len) would be after
+    /// [`remove`](FastSet::remove)ing `key`, without changing the set: one
+    /// less if `key` is present, and the same if it's absent or out of range.
+    pub fn len_after_remove(&self, key: usize) -> usize {
+        self.len - self.contains(key) as usize
+    }
+
+    /// Checks whether adding all of `keys` would push the density
+    /// `len / cap` above `max_density`, e.g. to switch to a denser
+    /// representation before a bulk add. `keys` is assumed to be distinct:
+    /// an absent key repeated in it is counted once per repetition. Keys that
+    /// are out of range are assumed not to be there at all, and a set with
+    /// capacity 0 never exceeds any density. Does not modify the set. Runs in
+    /// `O(keys.len())` time.
+    pub fn would_exceed_density(&self, keys: &[usize], max_density: f64) -> bool {
+        if self.cap == 0 {
+            return false;
+        }
+        let added = keys.iter().filter(|&&key| self.would_insert(key)).count();
+        (self.len + added) as f64 / self.cap as f64 > max_density
+    }
+
     /// Adds the given key to the set. Returns an `OutOfBounds` if `key > self.cap()`.
     /// No-op if `self.contains(key)`.
     pub fn add(&mut self, key: usize) -> Result<(), OutOfBounds> {
@@ -102,9 +616,155 @@ impl FastSet {
         Ok(())
     }
 
+    /// Adds the given key to the set, like [`add`](FastSet::add), and returns
+    /// whether it was newly added (`false` if it was already present).
+    pub fn add_reporting(&mut self, key: usize) -> Result<bool, OutOfBounds> {
+        if key >= self.cap {
+            return Err(OutOfBounds { cap: self.cap, key });
+        }
+        unsafe {
+            if self.unchecked_contains(key) {
+                return Ok(false);
+            }
+            self.unchecked_add(key);
+        }
+        Ok(true)
+    }
+
+    /// Pushes `key` onto the end of [`keys`](FastSet::keys), for treating the
+    /// set as a stack of unique keys, and returns whether it was newly added.
+    /// Unlike a normal stack this deduplicates: pushing a key that's already
+    /// present leaves it where it is and returns `false`. This is the same as
+    /// [`add_reporting`](FastSet::add_reporting).
+    pub fn push(&mut self, key: usize) -> Result<bool, OutOfBounds> {
+        self.add_reporting(key)
+    }
+
+    /// Replaces `old_key` with `new_key`, returning whether the set changed.
+    /// If `old_key` isn't present (or equals `new_key`) nothing happens; if
+    /// `new_key` is already present, `old_key` is simply removed. Returns an
+    /// `OutOfBounds`, leaving the set unchanged, if either key is
+    /// `>= self.cap()`.
+    pub fn rename(&mut self, old_key: usize, new_key: usize) -> Result<bool, OutOfBounds> {
+        for key in [old_key, new_key] {
+            if key >= self.cap {
+                return Err(OutOfBounds { cap: self.cap, key });
+            }
+        }
+        unsafe {
+            if old_key == new_key || !self.unchecked_contains(old_key) {
+                return Ok(false);
+            }
+            self.unchecked_remove(old_key);
+            if !self.unchecked_contains(new_key) {
+                self.unchecked_add(new_key);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Removes the given key from the set, like [`remove`](FastSet::remove),
+    /// and returns whether it was present.
+    pub fn remove_reporting(&mut self, key: usize) -> Result<bool, OutOfBounds> {
+        if key >= self.cap {
+            return Err(OutOfBounds { cap: self.cap, key });
+        }
+        unsafe {
+            if !self.unchecked_contains(key) {
+                return Ok(false);
+            }
+            self.unchecked_remove(key);
+        }
+        Ok(true)
+    }
+
+    /// Adds the given key to the set, growing the capacity first if
+    /// `key >= self.cap()`. The capacity at least doubles on each growth, so a
+    /// run of increasing keys reallocates only `O(log(max_key))` times.
+    /// Returns an error, leaving the set unchanged, if the needed capacity
+    /// can't be allocated.
+    pub fn add_grow(&mut self, key: usize) -> Result<(), LayoutError> {
+        self.add_grow_reporting(key).map(|_| ())
+    }
+
+    /// Like [`add_grow`](FastSet::add_grow), but returns whether the capacity
+    /// had to grow (and so the arrays were reallocated) for this key.
+    pub fn add_grow_reporting(&mut self, key: usize) -> Result<bool, LayoutError> {
+        let grew = key >= self.cap;
+        if grew {
+            let max_cap = isize::MAX as usize / std::mem::size_of::<usize>();
+            let doubled = self.cap.saturating_mul(2).min(max_cap);
+            self.resize_preserving(key.saturating_add(1).max(doubled))?;
+        }
+        // Can't fail, since key < self.cap
+        self.add(key).unwrap();
+        Ok(grew)
+    }
+
+    /// Adds each key yielded by `iter`, returning how many were newly added.
+    /// Returns an `OutOfBounds` at the first key that is `>= self.cap()`; the
+    /// keys before it have already been added.
+    pub fn add_all_reporting<I: IntoIterator<Item = usize>>(
+        &mut self,
+        iter: I,
+    ) -> Result<usize, OutOfBounds> {
+        let mut changed = 0;
+        for key in iter {
+            changed += self.add_reporting(key)? as usize;
+        }
+        Ok(changed)
+    }
+
+    /// Like [`add_all_reporting`](FastSet::add_all_reporting), but for keys
+    /// in ascending order, which need not be distinct. Returns how many keys
+    /// were newly added. Since the last key is the largest, it is checked up
+    /// front: if it is `>= self.cap()`, returns an `OutOfBounds` for the first
+    /// such key, leaving the set unchanged. Each key is still bounds checked
+    /// as it is added, so if `sorted` isn't in ascending order this returns an
+    /// `OutOfBounds` at the first key that is `>= self.cap()`, with the keys
+    /// before it already added.
+    pub fn merge_sorted_run(&mut self, sorted: &[usize]) -> Result<usize, OutOfBounds> {
+        if let Some(&last) = sorted.last() {
+            if last >= self.cap {
+                let i = sorted.partition_point(|&key| key < self.cap);
+                return Err(OutOfBounds {
+                    cap: self.cap,
+                    key: sorted[i.min(sorted.len() - 1)],
+                });
+            }
+        }
+        let mut changed = 0;
+        for &key in sorted {
+            // Still bounds check, in case sorted isn't actually sorted
+            if key >= self.cap {
+                return Err(OutOfBounds { cap: self.cap, key });
+            }
+            if unsafe { !self.unchecked_contains(key) } {
+                unsafe { self.unchecked_add(key) };
+                changed += 1;
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Removes each key yielded by `iter`, returning how many were present.
+    /// Returns an `OutOfBounds` at the first key that is `>= self.cap()`; the
+    /// keys before it have already been removed.
+    pub fn remove_all_reporting<I: IntoIterator<Item = usize>>(
+        &mut self,
+        iter: I,
+    ) -> Result<usize, OutOfBounds> {
+        let mut changed = 0;
+        for key in iter {
+            changed += self.remove_reporting(key)? as usize;
+        }
+        Ok(changed)
+    }
+
     /// Removes all elements from the set.
     pub fn clear(&mut self) {
         self.len = 0;
+        self.sorted_valid = false;
     }
 
     /// Returns a slice containing the keys of the set, in arbitrary order.
@@ -112,113 +772,2535 @@ impl FastSet {
         unsafe { std::slice::from_raw_parts(self.backref, self.len) }
     }
 
-    /// Checks whether the set contains the given key. The key must be less than
-    /// `self.cap()`.
-    pub unsafe fn unchecked_contains(&self, key: usize) -> bool {
-        // We are assuming key < cap, in particular key < isize::MAX
-        let index = *self.sparse.offset(key as isize);
-        if index >= self.len {
-            return false;
-        }
-        *self.backref.offset(index as isize) == key
+    /// Returns an iterator over the keys in the reverse of
+    /// [`keys`](FastSet::keys) order, i.e. `self.keys().iter().rev()`. With
+    /// only additions that's most recent first, which suits LIFO processing,
+    /// but it's only "insertion-ish": each removal moves the last key into
+    /// the removed key's slot.
+    pub fn iter_rev_insertion(&self) -> impl Iterator<Item = usize> + '_ {
+        self.keys().iter().rev().copied()
     }
 
-    /// Adds the given key to the set. The key must be less than `self.cap()`
-    /// and not already in the set.
-    pub unsafe fn unchecked_add(&mut self, key: usize) {
-        // Assuming key < cap and key is not already in the set
-        *self.sparse.offset(key as isize) = self.len;
-        *self.backref.offset(self.len as isize) = key;
-        self.len += 1;
+    /// Returns the index of `key` in [`keys`](FastSet::keys), or `None` if it
+    /// isn't present. With only additions this is the order of insertion, so
+    /// it can help analyze access patterns.
+    ///
+    /// **Removals scramble these positions:** removing a key moves the last
+    /// key into its slot, so after any removal a key's position no longer
+    /// says when it was inserted. Treat the result as a snapshot, valid only
+    /// until the next mutation.
+    pub fn insertion_positions(&self, key: usize) -> Option<usize> {
+        self.position(key)
     }
 
-    /// Removes the given key from the set. The key must be less than
-    /// `self.cap()` and already in the set.
-    pub unsafe fn unchecked_remove(&mut self, key: usize) {
-        // Assuming self.contains(key) so in particular key < cap
-        let to_delete_index = *self.sparse.offset(key as isize);
-        let to_delete = self.backref.offset(to_delete_index as isize);
-        let last = self.backref.offset(self.len as isize - 1);
-        let moved_key = *last;
-        *to_delete = moved_key;
-        *self.sparse.offset(moved_key as isize) = to_delete_index;
-        self.len -= 1;
+    /// Copies as many keys as fit into `out`, in the same order as
+    /// [`keys`](FastSet::keys), and returns how many were copied: the smaller
+    /// of `self.len()` and `out.len()`. If `out` is too short only a prefix of
+    /// `keys()` is copied. Never allocates.
+    pub fn copy_keys_to(&self, out: &mut [usize]) -> usize {
+        let n = self.len.min(out.len());
+        out[..n].copy_from_slice(&self.keys()[..n]);
+        n
     }
-}
 
-impl Drop for FastSet {
-    fn drop(&mut self) {
-        let layout = Layout::array::<usize>(self.cap).unwrap(); // If this was gonna fail it would have at New()
-        unsafe {
-            dealloc(self.sparse as *mut u8, layout);
-            dealloc(self.backref as *mut u8, layout);
+    /// Returns a snapshot of the current keys, in insertion order, that
+    /// [`load`](FastSet::load) can later restore. Takes `O(self.len())` time.
+    pub fn save(&self) -> Vec<usize> {
+        self.keys().to_vec()
+    }
+
+    /// Replaces the contents of the set with exactly the keys in `snapshot`,
+    /// such as one returned by [`save`](FastSet::save). Takes
+    /// `O(snapshot.len())` time. Returns an `OutOfBounds` for the first key
+    /// that is `>= self.cap()`, leaving the set unchanged.
+    pub fn load(&mut self, snapshot: &[usize]) -> Result<(), OutOfBounds> {
+        if let Some(&key) = snapshot.iter().find(|&&key| key >= self.cap) {
+            return Err(OutOfBounds { cap: self.cap, key });
+        }
+        self.clear();
+        for &key in snapshot {
+            unsafe {
+                if !self.unchecked_contains(key) {
+                    self.unchecked_add(key);
+                }
+            }
         }
+        Ok(())
     }
-}
 
-/// Iteration runs in `O(self.len())` time.
-impl<'a> IntoIterator for &'a FastSet {
-    type Item = &'a usize;
-    type IntoIter = std::slice::Iter<'a, usize>;
+    /// Splits the keys, in insertion order, into `n` contiguous chunks whose
+    /// lengths differ by at most one, with the longer chunks first. Every key
+    /// is in exactly one chunk, and if `n > self.len()` the trailing chunks
+    /// are empty. Useful for handing the keys out to a fixed number of
+    /// workers. Runs in `O(self.len() + n)` time.
+    ///
+    /// # Panics
+    /// Panics if `n` is 0.
+    pub fn split_n(&self, n: usize) -> Vec<Vec<usize>> {
+        assert!(n > 0, "n must be positive");
+        let (size, extra) = (self.len / n, self.len % n);
+        let mut rest = self.keys();
+        (0..n)
+            .map(|i| {
+                let (chunk, tail) = rest.split_at(size + usize::from(i < extra));
+                rest = tail;
+                chunk.to_vec()
+            })
+            .collect()
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.keys().into_iter()
+    /// Returns the largest key in `keys`, or `None` if `keys` is empty.
+    pub fn max_key(keys: &[usize]) -> Option<usize> {
+        keys.iter().copied().max()
     }
-}
 
-impl Clone for FastSet {
-    /// Cloning a `FastSet` takes `O(self.len())` time.
-    fn clone(&self) -> Self {
-        let mut ret = Self::new(self.cap).unwrap();
+    /// Checks whether every key in `keys` is less than `self.cap()`, i.e.
+    /// whether adding them all would succeed. Does not modify the set.
+    /// Runs in `O(keys.len())` time, stopping at the first out-of-range key.
+    pub fn fits(&self, keys: &[usize]) -> bool {
+        keys.iter().all(|&key| key < self.cap)
+    }
+
+    /// Checks that every key in the set is less than `self.cap()`, returning
+    /// an `OutOfBounds` for the first one that isn't. Safe methods maintain
+    /// this, so it's a debugging aid for code that builds sets with
+    /// [`unchecked_add`](FastSet::unchecked_add) or
+    /// [`from_sorted_trusted`](FastSet::from_sorted_trusted). Note that
+    /// breaking their contracts is already undefined behavior, which this may
+    /// or may not catch. Runs in `O(self.len())` time.
+    pub fn validate_bounds(&self) -> Result<(), OutOfBounds> {
+        match self.keys().iter().find(|&&key| key >= self.cap) {
+            Some(&key) => Err(OutOfBounds { cap: self.cap, key }),
+            None => Ok(()),
+        }
+    }
+
+    /// Adds the given key if it is absent, and removes it if it is present.
+    /// Returns an `OutOfBounds` if `key >= self.cap()`.
+    pub fn toggle(&mut self, key: usize) -> Result<(), OutOfBounds> {
+        if key >= self.cap {
+            return Err(OutOfBounds { cap: self.cap, key });
+        }
         unsafe {
-            for key in self {
-                ret.unchecked_add(*key);
+            if self.unchecked_contains(key) {
+                self.unchecked_remove(key);
+            } else {
+                self.unchecked_add(key);
             }
         }
-        ret
+        Ok(())
     }
 
-    /// Gives the allocator the opportunity to be smart; avoids allocation
-    /// entirely if `self.cap() == source.cap()`.
-    fn clone_from(&mut self, source: &Self) {
-        if self.cap == source.cap {
-            self.clear();
-        } else {
-            let old_layout = Layout::array::<usize>(self.cap).unwrap();
-            let new_layout = Layout::array::<usize>(source.cap).unwrap();
-            if self.cap > source.cap {
-                // shrinking, safe to use realloc
-                unsafe {
-                    self.sparse = realloc(self.sparse as *mut u8, old_layout, new_layout.size())
-                        as *mut usize;
+    /// Toggles each key yielded by `iter`, in order. A key yielded twice is
+    /// toggled twice. Returns an `OutOfBounds` at the first key that is
+    /// `>= self.cap()`; the keys before it have already been toggled.
+    pub fn toggle_all<I: IntoIterator<Item = usize>>(
+        &mut self,
+        iter: I,
+    ) -> Result<(), OutOfBounds> {
+        for key in iter {
+            self.toggle(key)?;
+        }
+        Ok(())
+    }
+
+    /// Applies each of `commands` in order, by calling the corresponding
+    /// method. Replaying a recorded log this way on a set with the same
+    /// starting contents reproduces the final state. Returns an
+    /// `OutOfBounds` at the first command whose key is `>= self.cap()`; the
+    /// commands before it have already been applied.
+    pub fn apply_commands<I: IntoIterator<Item = SetCommand>>(
+        &mut self,
+        commands: I,
+    ) -> Result<(), OutOfBounds> {
+        for command in commands {
+            match command {
+                SetCommand::Add(key) => self.add(key)?,
+                SetCommand::Remove(key) => self.remove(key)?,
+                SetCommand::Clear => self.clear(),
+                SetCommand::Toggle(key) => self.toggle(key)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns a set with capacity `cap` containing the keys that appear in
+    /// exactly one of `sets`. Keys `>= cap` are ignored, so inputs with a
+    /// larger capacity are effectively clamped to `cap`. Runs in time
+    /// proportional to the total length of `sets`.
+    ///
+    /// # Panics
+    /// Panics if `cap` is greater than `isize::MAX`.
+    pub fn unique_across<'a, I: IntoIterator<Item = &'a FastSet>>(sets: I, cap: usize) -> FastSet {
+        let mut once = Self::new(cap).unwrap();
+        let mut many = Self::new(cap).unwrap();
+        for set in sets {
+            for &key in set {
+                if key >= cap {
+                    continue;
                 }
-            } else {
-                // growing, use alloc_zeroed
                 unsafe {
-                    dealloc(self.sparse as *mut u8, old_layout);
-                    self.sparse = alloc_zeroed(new_layout) as *mut usize;
+                    if many.unchecked_contains(key) {
+                        continue;
+                    }
+                    if once.unchecked_contains(key) {
+                        once.unchecked_remove(key);
+                        many.unchecked_add(key);
+                    } else {
+                        once.unchecked_add(key);
+                    }
                 }
             }
-            unsafe {
-                self.backref =
-                    realloc(self.backref as *mut u8, old_layout, new_layout.size()) as *mut usize;
+        }
+        once
+    }
+
+    /// Returns the number of distinct keys across all of `sets`, ignoring any
+    /// keys `>= cap`, without building the union. Allocates one scratch set
+    /// of capacity `cap`; use [`union_len_many_with`](FastSet::union_len_many_with)
+    /// to reuse one instead. Runs in time proportional to the total length of
+    /// `sets`.
+    ///
+    /// # Panics
+    /// Panics if `cap` is greater than `isize::MAX`.
+    pub fn union_len_many<'a, I: IntoIterator<Item = &'a FastSet>>(sets: I, cap: usize) -> usize {
+        Self::union_len_many_with(sets, &mut Self::new(cap).unwrap())
+    }
+
+    /// Like [`union_len_many`](FastSet::union_len_many), but uses `scratch`
+    /// (which is cleared first) to track the keys seen, ignoring any keys
+    /// `>= scratch.cap()`. On return, `scratch` holds the union.
+    pub fn union_len_many_with<'a, I: IntoIterator<Item = &'a FastSet>>(
+        sets: I,
+        scratch: &mut FastSet,
+    ) -> usize {
+        scratch.clear();
+        for set in sets {
+            for &key in set {
+                if key < scratch.cap && unsafe { !scratch.unchecked_contains(key) } {
+                    unsafe { scratch.unchecked_add(key) };
+                }
             }
-            self.len = 0;
-            self.cap = source.cap;
         }
-        for key in source {
-            unsafe {
-                self.unchecked_add(*key);
+        scratch.len
+    }
+
+    /// Checks whether no key appears in more than one of `sets`, stopping at
+    /// the first collision. Keys `>= cap` are ignored. Allocates one scratch
+    /// set of capacity `cap`; use [`all_disjoint_with`](FastSet::all_disjoint_with)
+    /// to reuse one instead. Runs in time proportional to the total length of
+    /// `sets`.
+    ///
+    /// # Panics
+    /// Panics if `cap` is greater than `isize::MAX`.
+    pub fn all_disjoint<'a, I: IntoIterator<Item = &'a FastSet>>(sets: I, cap: usize) -> bool {
+        Self::all_disjoint_with(sets, &mut Self::new(cap).unwrap())
+    }
+
+    /// Like [`all_disjoint`](FastSet::all_disjoint), but uses `scratch`
+    /// (which is cleared first) to track the keys seen, ignoring any keys
+    /// `>= scratch.cap()`.
+    pub fn all_disjoint_with<'a, I: IntoIterator<Item = &'a FastSet>>(
+        sets: I,
+        scratch: &mut FastSet,
+    ) -> bool {
+        scratch.clear();
+        for set in sets {
+            for &key in set {
+                if key < scratch.cap {
+                    if unsafe { scratch.unchecked_contains(key) } {
+                        return false;
+                    }
+                    unsafe { scratch.unchecked_add(key) };
+                }
             }
         }
+        true
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Returns how many of `sets` contain `key`. Sets too small to hold `key`
+    /// simply don't contain it. Runs in `O(1)` time per set.
+    pub fn containment_count<'a, I: IntoIterator<Item = &'a FastSet>>(
+        sets: I,
+        key: usize,
+    ) -> usize {
+        sets.into_iter().filter(|set| set.contains(key)).count()
+    }
 
-    #[test]
+    /// Returns the `n × n` matrix whose entry `[i][j]` is
+    /// `sets[i].intersection_len(&sets[j])`, for `n = sets.len()`. The
+    /// diagonal holds each set's length. The matrix is symmetric, so only
+    /// the upper triangle is computed and then mirrored: that's
+    /// `n * (n - 1) / 2` intersection counts, each taking the smaller
+    /// set's length, and nothing is allocated besides the matrix.
+    pub fn overlap_matrix(sets: &[FastSet]) -> Vec<Vec<usize>> {
+        let n = sets.len();
+        let mut matrix = vec![vec![0; n]; n];
+        for i in 0..n {
+            matrix[i][i] = sets[i].len;
+            for j in i + 1..n {
+                let shared = sets[i].intersection_len(&sets[j]);
+                matrix[i][j] = shared;
+                matrix[j][i] = shared;
+            }
+        }
+        matrix
+    }
+
+    /// Returns the keys of the set in ascending order. Runs in
+    /// `O(self.len() * log(self.len()))` time.
+    pub fn to_sorted_vec(&self) -> Vec<usize> {
+        let mut keys = self.keys().to_vec();
+        keys.sort_unstable();
+        keys
+    }
+
+    /// Returns a `Vec<bool>` of length `self.cap()` whose element `k` is
+    /// `self.contains(k)`; the inverse of [`from_bool_slice`](FastSet::from_bool_slice).
+    /// Runs in `O(self.cap())` time.
+    pub fn to_bool_vec(&self) -> Vec<bool> {
+        let mut mask = vec![false; self.cap];
+        for &key in self {
+            mask[key] = true;
+        }
+        mask
+    }
+
+    /// Returns the number of keys present in both `self` and `other`, without
+    /// allocating. Runs in `O(min(self.len(), other.len()))` time.
+    pub fn intersection_len(&self, other: &FastSet) -> usize {
+        let (small, large) = if self.len <= other.len {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        small
+            .keys()
+            .iter()
+            .filter(|&&key| large.contains(key))
+            .count()
+    }
+
+    /// Returns `(self.intersection_len(other), disjoint)` in a single pass,
+    /// where `disjoint` is whether the sets share no keys. Although
+    /// disjointness is settled by the first shared key, the count still needs
+    /// the whole pass, so this always takes `O(min(self.len(), other.len()))`
+    /// time; use [`intersection_len`](FastSet::intersection_len) alone if
+    /// that's all you need.
+    pub fn intersection_report(&self, other: &FastSet) -> (usize, bool) {
+        let shared = self.intersection_len(other);
+        (shared, shared == 0)
+    }
+
+    /// Checks whether `self` and `other` share no keys, stopping at the first
+    /// shared one. Runs in `O(min(self.len(), other.len()))` time.
+    pub fn is_disjoint(&self, other: &FastSet) -> bool {
+        let (small, large) = if self.len <= other.len {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        !small.keys().iter().any(|&key| large.contains(key))
+    }
+
+    /// Checks whether `self` and `other` partition `0..cap`: they are
+    /// disjoint and every key in `0..cap` is in one of them. Given
+    /// disjointness, that holds iff both only hold keys `< cap` and their
+    /// lengths add up to `cap`. Runs in `O(min(self.len(), other.len()))`
+    /// time, plus `O(len)` for each set whose capacity exceeds `cap`.
+    pub fn partitions_with(&self, other: &FastSet, cap: usize) -> bool {
+        let within = |set: &FastSet| set.cap <= cap || set.keys().iter().all(|&key| key < cap);
+        self.len.checked_add(other.len) == Some(cap)
+            && within(self)
+            && within(other)
+            && self.is_disjoint(other)
+    }
+
+    /// Checks whether `other` is exactly the complement of `self`: both have
+    /// the same capacity, and every key in `0..cap` is in exactly one of
+    /// them. Sets with different capacities are never complements, even if
+    /// their keys happen to partition one of the ranges.
+    /// Runs in `O(min(self.len(), other.len()))` time.
+    pub fn is_complement_of(&self, other: &FastSet) -> bool {
+        self.cap == other.cap && self.partitions_with(other, self.cap)
+    }
+
+    /// Classifies how `self` relates to `other` as a [`Relation`], from one
+    /// intersection count and the two lengths. Capacities don't matter.
+    /// Runs in `O(min(self.len(), other.len()))` time.
+    pub fn relate(&self, other: &FastSet) -> Relation {
+        let shared = self.intersection_len(other);
+        match (shared == self.len, shared == other.len) {
+            (true, true) => Relation::Equal,
+            (true, false) => Relation::Subset,
+            (false, true) => Relation::Superset,
+            _ if shared == 0 => Relation::Disjoint,
+            _ => Relation::Overlapping,
+        }
+    }
+
+    /// Returns the Dice coefficient `2 * |A ∩ B| / (|A| + |B|)` of the two sets.
+    /// Two empty sets are considered identical, with a coefficient of `1.0`.
+    /// Runs in `O(min(self.len(), other.len()))` time.
+    pub fn dice(&self, other: &FastSet) -> f64 {
+        let total = self.len + other.len;
+        if total == 0 {
+            return 1.0;
+        }
+        2.0 * self.intersection_len(other) as f64 / total as f64
+    }
+
+    /// Returns the sum over `others` of `|self Δ other|`, the number of keys
+    /// in exactly one of `self` and `other`. This is `0` only if every set in
+    /// `others` equals `self`, and grows with how far `self` deviates from
+    /// them in aggregate. Each term is computed as
+    /// `|self| + |other| - 2 * |self ∩ other|` with
+    /// [`intersection_len`](FastSet::intersection_len), so nothing is
+    /// allocated and it runs in time proportional to the sum of
+    /// `min(self.len(), other.len())`.
+    pub fn total_symmetric_difference<'a, I: IntoIterator<Item = &'a FastSet>>(
+        &self,
+        others: I,
+    ) -> usize {
+        others
+            .into_iter()
+            .map(|other| self.len + other.len - 2 * self.intersection_len(other))
+            .sum()
+    }
+
+    /// Reads `count` keys from `reader` and adds them to the set. Each key is
+    /// encoded as 8 little-endian bytes (a `u64`). Keys are read in chunks, so
+    /// `reader` need not be buffered, and no intermediate `Vec` is allocated.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidData`] wrapping an
+    /// `OutOfBounds` at the first key that is `>= self.cap()`; the keys read
+    /// before it have already been added. Errors from `reader` (including
+    /// [`io::ErrorKind::UnexpectedEof`] if it runs out early) are passed through.
+    pub fn load_keys<R: Read>(&mut self, reader: &mut R, count: usize) -> io::Result<()> {
+        const CHUNK: usize = 512;
+        let mut buf = [0u8; CHUNK * 8];
+        let mut remaining = count;
+        while remaining > 0 {
+            let n = remaining.min(CHUNK);
+            reader.read_exact(&mut buf[..n * 8])?;
+            for bytes in buf[..n * 8].chunks_exact(8) {
+                let raw = u64::from_le_bytes(bytes.try_into().unwrap());
+                let key = usize::try_from(raw).unwrap_or(usize::MAX);
+                self.add(key)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            }
+            remaining -= n;
+        }
+        Ok(())
+    }
+
+    /// Returns the capacity needed to hold the union of `self` and `other`,
+    /// i.e. `max(self.cap(), other.cap())`. A `dest` of at least this capacity
+    /// never fails in [`union_into`](FastSet::union_into).
+    pub fn union_cap(&self, other: &FastSet) -> usize {
+        self.cap.max(other.cap)
+    }
+
+    /// Returns the capacity needed to hold the intersection of `self` and
+    /// `other`, i.e. `min(self.cap(), other.cap())`. A `dest` of at least this
+    /// capacity never fails in [`intersection_into`](FastSet::intersection_into).
+    pub fn intersection_cap(&self, other: &FastSet) -> usize {
+        self.cap.min(other.cap)
+    }
+
+    /// Clears `dest` and fills it with the keys present in `self` or `other`.
+    /// Returns an `OutOfBounds` if some such key is `>= dest.cap()`, which
+    /// can't happen if `dest.cap() >= self.union_cap(other)`.
+    /// Runs in `O(self.len() + other.len())` time.
+    pub fn union_into(&self, other: &FastSet, dest: &mut FastSet) -> Result<(), OutOfBounds> {
+        dest.clear();
+        for &key in self.keys().iter().chain(other.keys()) {
+            dest.add(key)?;
+        }
+        Ok(())
+    }
+
+    /// Adds every key of `other` to the set and returns the keys that were
+    /// newly added, in `other`'s order. If `other.cap()` is larger, the
+    /// set's capacity is first grown to match, keeping every key; it is
+    /// never shrunk. Because of that growth no key can be out of bounds, so
+    /// this currently always returns `Ok`. Takes `O(other.len())` time, plus
+    /// the cost of growing.
+    pub fn union_with_reporting(&mut self, other: &FastSet) -> Result<Vec<usize>, OutOfBounds> {
+        if other.cap > self.cap {
+            // Can't fail, since the layout was valid for other
+            self.resize_preserving(other.cap).unwrap();
+        }
+        let mut added = Vec::new();
+        for &key in other.keys() {
+            if self.add_reporting(key)? {
+                added.push(key);
+            }
+        }
+        Ok(added)
+    }
+
+    /// Clears `dest` and fills it with the keys present in both `self` and
+    /// `other`. Returns an `OutOfBounds` if some such key is `>= dest.cap()`,
+    /// which can't happen if `dest.cap() >= self.intersection_cap(other)`.
+    /// Runs in `O(min(self.len(), other.len()))` time.
+    pub fn intersection_into(
+        &self,
+        other: &FastSet,
+        dest: &mut FastSet,
+    ) -> Result<(), OutOfBounds> {
+        let (small, large) = if self.len <= other.len {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        dest.clear();
+        for &key in small {
+            if large.contains(key) {
+                dest.add(key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns an iterator over the keys of `self` that are also in `other`,
+    /// in `self`'s insertion order. This is a lazy, allocation-free
+    /// counterpart to [`intersection_into`](FastSet::intersection_into);
+    /// keys beyond `other.cap()` are simply not in `other`. Iterating to the
+    /// end takes `O(self.len())` time.
+    pub fn iter_in<'a>(&'a self, other: &'a FastSet) -> impl Iterator<Item = usize> + 'a {
+        self.keys()
+            .iter()
+            .copied()
+            .filter(move |&key| other.contains(key))
+    }
+
+    /// Returns an iterator over every key in either set, each exactly once,
+    /// tagged with the [`Source`] it came from. The order is unspecified
+    /// beyond this: first `self`'s keys in insertion order, tagged `Both` or
+    /// `SelfOnly`, then `other`'s remaining keys in its insertion order,
+    /// tagged `OtherOnly`. Iterating to the end takes
+    /// `O(self.len() + other.len())` time and allocates nothing.
+    pub fn tagged_union<'a>(
+        &'a self,
+        other: &'a FastSet,
+    ) -> impl Iterator<Item = (usize, Source)> + 'a {
+        let ours = self.keys().iter().map(move |&key| {
+            let source = if other.contains(key) {
+                Source::Both
+            } else {
+                Source::SelfOnly
+            };
+            (key, source)
+        });
+        let theirs = other
+            .keys()
+            .iter()
+            .filter(move |&&key| !self.contains(key))
+            .map(|&key| (key, Source::OtherOnly));
+        ours.chain(theirs)
+    }
+
+    /// Returns a set with capacity `self.cap()` containing the keys of `self`
+    /// that are not in `other`, in `self`'s insertion order. Runs in
+    /// `O(self.len())` time.
+    pub fn difference(&self, other: &FastSet) -> FastSet {
+        let mut ret = self.empty_like();
+        for &key in self.keys() {
+            if !other.contains(key) {
+                unsafe { ret.unchecked_add(key) };
+            }
+        }
+        ret
+    }
+
+    /// Returns the keys in `other` but not in `self`, i.e. what's new in
+    /// `other`, as a set with capacity `other.cap()`. This is `self`'s
+    /// complement intersected with `other`, and is the same as
+    /// `other.difference(self)`.
+    pub fn complement_intersect(&self, other: &FastSet) -> FastSet {
+        other.difference(self)
+    }
+
+    /// Clears `dest` and fills it with the keys present in exactly one of
+    /// `self` and `other`. Returns an `OutOfBounds` if some such key is
+    /// `>= dest.cap()`, which can't happen if `dest.cap() >= self.union_cap(other)`.
+    /// Runs in `O(self.len() + other.len())` time.
+    pub fn symmetric_difference_into(
+        &self,
+        other: &FastSet,
+        dest: &mut FastSet,
+    ) -> Result<(), OutOfBounds> {
+        dest.clear();
+        for &key in self {
+            if !other.contains(key) {
+                dest.add(key)?;
+            }
+        }
+        for &key in other {
+            if !self.contains(key) {
+                dest.add(key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks whether every key in `range` is present. Returns `true` for an
+    /// empty range, and `false` if `range` reaches past `self.cap()`. Since a
+    /// range longer than the set can't be covered, this probes at most
+    /// `self.len()` keys, so it runs in `O(min(range.len(), self.len()))` time.
+    pub fn contains_range(&self, range: Range<usize>) -> bool {
+        if range.is_empty() {
+            return true;
+        }
+        if range.end > self.cap || range.len() > self.len {
+            return false;
+        }
+        range
+            .into_iter()
+            .all(|key| unsafe { self.unchecked_contains(key) })
+    }
+
+    /// Checks whether every key in `start..=end` is present, after clamping
+    /// `end` to `self.cap() - 1`; keys the set can't hold are ignored rather
+    /// than counted as missing. This avoids overflowing when converting
+    /// `end == usize::MAX` to a half-open range. Returns `true` if the
+    /// clamped range is empty. Otherwise this is
+    /// [`contains_range`](FastSet::contains_range) on the clamped range, with
+    /// the same running time.
+    pub fn contains_inclusive_range(&self, start: usize, end: usize) -> bool {
+        let end = end.min(self.cap.saturating_sub(1));
+        if start > end || start >= self.cap {
+            return true;
+        }
+        self.contains_range(start..end + 1)
+    }
+
+    /// Returns the number of keys in `range`. Runs in
+    /// `O(min(range.len(), self.len()))` time, by probing each key of the
+    /// range or checking each key of the set, whichever is fewer.
+    pub fn count_in_range(&self, range: Range<usize>) -> usize {
+        let range = range.start..range.end.min(self.cap);
+        if range.len() <= self.len {
+            range
+                .filter(|&key| unsafe { self.unchecked_contains(key) })
+                .count()
+        } else {
+            self.keys().iter().filter(|key| range.contains(key)).count()
+        }
+    }
+
+    /// Returns the number of keys in each of `ranges`. If the ranges are in
+    /// ascending order and don't overlap, each key is assigned to its range
+    /// by binary search, taking `O(self.len() * log(ranges.len()))` time.
+    /// Otherwise each range is counted separately with
+    /// [`count_in_range`](FastSet::count_in_range).
+    pub fn count_in_ranges(&self, ranges: &[Range<usize>]) -> Vec<usize> {
+        let disjoint_sorted = ranges.iter().all(|range| range.start <= range.end)
+            && ranges.windows(2).all(|pair| pair[0].end <= pair[1].start);
+        if !disjoint_sorted {
+            return ranges
+                .iter()
+                .map(|range| self.count_in_range(range.clone()))
+                .collect();
+        }
+        let mut counts = vec![0; ranges.len()];
+        for &key in self.keys() {
+            let i = ranges.partition_point(|range| range.end <= key);
+            if i < ranges.len() && ranges[i].start <= key {
+                counts[i] += 1;
+            }
+        }
+        counts
+    }
+
+    /// Returns a vector of length `self.cap() + 1` whose entry `i` is the
+    /// number of keys less than `i`, so the rank of any key can then be
+    /// looked up in constant time. The last entry is `self.len()`. Runs in
+    /// `O(self.cap())` time.
+    pub fn membership_prefix_sum(&self) -> Vec<usize> {
+        let mut ranks = Vec::with_capacity(self.cap + 1);
+        let mut count = 0;
+        ranks.push(0);
+        for key in 0..self.cap {
+            count += unsafe { self.unchecked_contains(key) } as usize;
+            ranks.push(count);
+        }
+        ranks
+    }
+
+    /// Returns the maximal runs of consecutive keys in the set, as ranges in
+    /// ascending order. Runs in `O(self.len() * log(self.len()))` time.
+    pub fn to_runs(&self) -> Vec<Range<usize>> {
+        let mut runs: Vec<Range<usize>> = Vec::new();
+        for key in self.to_sorted_vec() {
+            match runs.last_mut() {
+                Some(run) if run.end == key => run.end += 1,
+                _ => runs.push(key..key + 1),
+            }
+        }
+        runs
+    }
+
+    /// Returns one set per run in [`to_runs`](FastSet::to_runs), each with
+    /// the same capacity as `self` and containing just that run's keys.
+    /// Note that this allocates `O(self.cap())` memory per run, which can be
+    /// expensive for a fragmented set with a large capacity.
+    pub fn split_into_runs(&self) -> Vec<FastSet> {
+        self.to_runs()
+            .into_iter()
+            .map(|run| {
+                let mut set = self.empty_like();
+                for key in run {
+                    unsafe { set.unchecked_add(key) };
+                }
+                set
+            })
+            .collect()
+    }
+
+    /// Returns an empty set with the same capacity as `self`.
+    fn empty_like(&self) -> FastSet {
+        Self::new(self.cap).unwrap() // Can't fail, since it didn't for self
+    }
+
+    /// Returns a new set with the same capacity as `self`, containing every key
+    /// of `self` that is not in `range`. Runs in `O(self.len())` time.
+    pub fn without_range(&self, range: Range<usize>) -> FastSet {
+        let range = range.start..range.end.min(self.cap);
+        let mut ret = self.empty_like();
+        for &key in self {
+            if !range.contains(&key) {
+                unsafe { ret.unchecked_add(key) };
+            }
+        }
+        ret
+    }
+
+    /// Returns a new set with the same capacity as `self`, without the keys
+    /// `start, start + step, start + 2 * step, ...`, e.g. to drop every
+    /// `step`th key. If `step` is 0 there's no progression to remove, so
+    /// this returns a copy of `self`. Runs in `O(self.len())` time.
+    pub fn without_stride(&self, start: usize, step: usize) -> FastSet {
+        if step == 0 {
+            return self.clone();
+        }
+        self.filter(|key| key < start || !(key - start).is_multiple_of(step))
+    }
+
+    /// Returns an iterator over a shuffled copy of the keys, leaving the set
+    /// itself untouched. Allocates a `Vec` of `self.len()` keys and runs in
+    /// `O(self.len())` time. Requires the `rand` feature.
+    #[cfg(feature = "rand")]
+    pub fn iter_shuffled<R: Rng>(&self, rng: &mut R) -> std::vec::IntoIter<usize> {
+        let mut keys = self.keys().to_vec();
+        for i in (1..keys.len()).rev() {
+            keys.swap(i, rng.random_range(0..=i));
+        }
+        keys.into_iter()
+    }
+
+    /// Returns an iterator over the keys in ascending order of `f(key)`, with
+    /// ties broken by ascending key so the order is deterministic. Copies the
+    /// keys and takes `O(self.len() * log(self.len()))` time, calling `f`
+    /// once per key.
+    pub fn iter_by_key<K: Ord, F: Fn(usize) -> K>(&self, f: F) -> std::vec::IntoIter<usize> {
+        let mut keys: Vec<(K, usize)> = self.keys().iter().map(|&key| (f(key), key)).collect();
+        keys.sort_unstable();
+        keys.into_iter()
+            .map(|(_, key)| key)
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// Returns `(max_len, max_key)`: the largest length the set has ever had
+    /// and the largest key ever added to it, or `0` for either if nothing has
+    /// been added. These are historical, so [`clear`](FastSet::clear) and
+    /// removals don't reset them, but a clone starts its own history from
+    /// its current contents. Useful for checking whether a smaller `cap`
+    /// would have sufficed.
+    pub fn high_water(&self) -> (usize, usize) {
+        (self.max_len_seen, self.max_key_seen)
+    }
+
+    /// Clears the set and changes its capacity to `new_cap`, for handing a
+    /// pooled set to its next user. Never reallocates if `new_cap` equals the
+    /// current capacity, and otherwise gives the allocator the chance to
+    /// resize in place. Returns an error, leaving the set unchanged, if
+    /// `new_cap` is greater than `isize::MAX`.
+    pub fn recycle(&mut self, new_cap: usize) -> Result<(), LayoutError> {
+        Layout::array::<usize>(new_cap)?;
+        self.clear();
+        if new_cap == self.cap {
+            return Ok(());
+        }
+        let events = unsafe {
+            let sparse_events = if new_cap < self.cap {
+                // shrinking, safe to use realloc
+                let (sparse, event) = realloc_array(self.sparse, self.cap, new_cap);
+                self.sparse = sparse;
+                [event, None]
+            } else {
+                // growing, use alloc_zeroed
+                let freed = dealloc_array(self.sparse, self.cap);
+                let (sparse, event) = alloc_array(new_cap, true);
+                self.sparse = sparse;
+                [freed, event]
+            };
+            let (backref, backref_event) = realloc_array(self.backref, self.cap, new_cap);
+            self.backref = backref;
+            [sparse_events[0], sparse_events[1], backref_event]
+        };
+        self.cap = new_cap;
+        self.report(events);
+        Ok(())
+    }
+
+    /// Changes the capacity to `new_cap`, keeping every key. All keys must be
+    /// less than `new_cap`. Returns an error, leaving the set unchanged, if
+    /// `new_cap` is greater than `isize::MAX`.
+    fn resize_preserving(&mut self, new_cap: usize) -> Result<(), LayoutError> {
+        Layout::array::<usize>(new_cap)?;
+        debug_assert!(self.keys().iter().all(|&key| key < new_cap));
+        if new_cap == self.cap {
+            return Ok(());
+        }
+        let events = unsafe {
+            let (sparse, sparse_event) = realloc_array(self.sparse, self.cap, new_cap);
+            self.sparse = sparse;
+            if new_cap > self.cap {
+                self.sparse.add(self.cap).write_bytes(0, new_cap - self.cap);
+            }
+            let (backref, backref_event) = realloc_array(self.backref, self.cap, new_cap);
+            self.backref = backref;
+            [sparse_event, backref_event]
+        };
+        self.cap = new_cap;
+        self.report(events);
+        Ok(())
+    }
+
+    /// Grows the capacity to `new_cap`, keeping every key, and adds all of
+    /// the new keys `old_cap..new_cap` if `new_present` is set. So if the set
+    /// tracks the complement of some selection, growing with `new_present`
+    /// leaves the new keys unselected. Does nothing if `new_cap <= self.cap()`.
+    /// Returns an error, leaving the set unchanged, if `new_cap` is greater
+    /// than `isize::MAX`.
+    pub fn grow_complement_semantics(
+        &mut self,
+        new_cap: usize,
+        new_present: bool,
+    ) -> Result<(), LayoutError> {
+        let old_cap = self.cap;
+        if new_cap <= old_cap {
+            return Ok(());
+        }
+        self.resize_preserving(new_cap)?;
+        if new_present {
+            for key in old_cap..new_cap {
+                unsafe { self.unchecked_add(key) };
+            }
+        }
+        Ok(())
+    }
+
+    /// Exports the set as a Bloom filter of `bits` bits using `hashes` hash
+    /// functions, packed into `ceil(bits / 64)` words with bit `b` stored as
+    /// `bit b % 64` of word `b / 64`. The filter answers "possibly present"
+    /// for every key in the set, and also for some keys that aren't; more
+    /// bits per key and a suitable number of hashes make those false
+    /// positives rarer. There is no way to turn a filter back into a set.
+    ///
+    /// Key `k` sets bits `(h1 + i * h2) % bits` for `i` in `0..hashes`, where
+    /// `h1 = splitmix64(k)` and `h2 = splitmix64(h1) | 1`, using the
+    /// SplitMix64 finalizer and wrapping `u64` arithmetic.
+    ///
+    /// # Panics
+    /// Panics if `bits` is 0.
+    pub fn to_bloom(&self, bits: usize, hashes: usize) -> Vec<u64> {
+        assert!(bits > 0, "a Bloom filter needs at least one bit");
+        let mut filter = vec![0u64; bits.div_ceil(64)];
+        for &key in self {
+            let h1 = mix64(key as u64);
+            let h2 = mix64(h1) | 1;
+            for i in 0..hashes as u64 {
+                let bit = (h1.wrapping_add(i.wrapping_mul(h2)) % bits as u64) as usize;
+                filter[bit / 64] |= 1 << (bit % 64);
+            }
+        }
+        filter
+    }
+
+    /// Checks the set against a `Vec<bool>`-style oracle: returns `true` iff
+    /// `self.contains(k) == reference[k]` for every `k < reference.len()`, and
+    /// the set has no keys `>= reference.len()`. So `reference` may be shorter
+    /// or longer than `self.cap()`, but a `true` at an index `>= self.cap()`
+    /// never matches. Runs in `O(reference.len())` time.
+    pub fn matches_reference(&self, reference: &[bool]) -> bool {
+        let mut expected = 0;
+        for (key, &present) in reference.iter().enumerate() {
+            if present {
+                if !self.contains(key) {
+                    return false;
+                }
+                expected += 1;
+            }
+        }
+        // Every expected key is present, so the set matches iff it has no others
+        expected == self.len
+    }
+
+    /// Returns the keys of the set in ascending order, like
+    /// [`to_sorted_vec`](FastSet::to_sorted_vec), but keeps the sorted keys
+    /// around so that later calls are `O(1)` until the set is next modified.
+    /// The buffer is reused when the keys need sorting again. The cache isn't
+    /// carried over by `clone`.
+    pub fn sorted_cached(&mut self) -> &[usize] {
+        if !self.sorted_valid {
+            let mut sorted = std::mem::take(&mut self.sorted);
+            sorted.clear();
+            sorted.extend_from_slice(self.keys());
+            sorted.sort_unstable();
+            self.sorted = sorted;
+            self.sorted_valid = true;
+        }
+        &self.sorted
+    }
+
+    /// Returns an iterator over the keys of the set in descending order.
+    /// Dense sets (see below) are scanned directly in `O(self.cap())` time
+    /// without allocating; sparse ones have their keys sorted in
+    /// `O(self.len() * log(self.len()))` time and memory proportional to
+    /// `self.len()`. The set counts as dense when `len * log2(len) >= cap`,
+    /// i.e. when sorting would cost at least as much as scanning.
+    pub fn iter_descending(&self) -> impl Iterator<Item = usize> + '_ {
+        let (scan, sorted) = if self.is_dense() {
+            let scan = (0..self.cap)
+                .rev()
+                .filter(|&key| unsafe { self.unchecked_contains(key) });
+            (Some(scan), None)
+        } else {
+            (None, Some(self.to_sorted_vec().into_iter().rev()))
+        };
+        scan.into_iter()
+            .flatten()
+            .chain(sorted.into_iter().flatten())
+    }
+
+    /// Whether scanning `0..cap` is no more expensive than sorting the keys.
+    fn is_dense(&self) -> bool {
+        let log_len = (usize::BITS - self.len.leading_zeros()) as usize;
+        self.len.saturating_mul(log_len) >= self.cap
+    }
+
+    /// Returns a map from each key to its index in ascending key order, so the
+    /// keys are relabelled as `0..self.len()`. The ordering makes the result
+    /// deterministic: the smallest key maps to 0, the largest to `len - 1`.
+    /// Runs in `O(self.len() * log(self.len()))` time.
+    pub fn to_index_map(&self) -> HashMap<usize, usize> {
+        self.to_sorted_vec()
+            .into_iter()
+            .enumerate()
+            .map(|(index, key)| (key, index))
+            .collect()
+    }
+
+    /// Returns the overlap (Szymkiewicz–Simpson) coefficient
+    /// `|A ∩ B| / min(|A|, |B|)` of the two sets. If either set is empty it is
+    /// trivially contained in the other, so the coefficient is `1.0`.
+    /// Runs in `O(min(self.len(), other.len()))` time.
+    pub fn overlap_coefficient(&self, other: &FastSet) -> f64 {
+        let smaller = self.len.min(other.len);
+        if smaller == 0 {
+            return 1.0;
+        }
+        self.intersection_len(other) as f64 / smaller as f64
+    }
+
+    /// Adds the given key to the set, like [`add`](FastSet::add), and returns
+    /// its position in [`keys`](FastSet::keys), so that a parallel array of
+    /// values can be kept in step with the set. A newly added key goes at the
+    /// end; a key already present keeps its position. Note that removing a
+    /// key moves the last key into the removed key's position.
+    /// Returns an `OutOfBounds` if `key >= self.cap()`.
+    pub fn add_at(&mut self, key: usize) -> Result<usize, OutOfBounds> {
+        if key >= self.cap {
+            return Err(OutOfBounds { cap: self.cap, key });
+        }
+        unsafe {
+            if self.unchecked_contains(key) {
+                Ok(*self.sparse.add(key))
+            } else {
+                self.unchecked_add(key);
+                Ok(self.len - 1)
+            }
+        }
+    }
+
+    /// Returns the position of `key` in [`keys`](FastSet::keys), or `None` if
+    /// it isn't present.
+    fn position(&self, key: usize) -> Option<usize> {
+        if self.contains(key) {
+            Some(unsafe { *self.sparse.add(key) })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the fraction of `expected`'s keys that are present in `self`,
+    /// i.e. `|self ∩ expected| / |expected|`. If `expected` is empty there's
+    /// nothing to miss, so the coverage is `1.0`.
+    /// Runs in `O(min(self.len(), expected.len()))` time.
+    pub fn coverage(&self, expected: &FastSet) -> f64 {
+        if expected.is_empty() {
+            return 1.0;
+        }
+        self.intersection_len(expected) as f64 / expected.len as f64
+    }
+
+    /// Returns, for each consecutive block of `window` keys in
+    /// `0..self.cap()`, the fraction of that block present in the set. The
+    /// result has `ceil(self.cap() / window)` entries; the last block may be
+    /// shorter than `window`, in which case it is divided by its actual size.
+    /// Runs in `O(self.len() + self.cap() / window)` time.
+    ///
+    /// # Panics
+    /// Panics if `window` is 0.
+    pub fn window_coverage(&self, window: usize) -> Vec<f64> {
+        assert!(window > 0, "window must be positive");
+        let blocks = self.cap.div_ceil(window);
+        let mut counts = vec![0usize; blocks];
+        for &key in self.keys() {
+            counts[key / window] += 1;
+        }
+        counts
+            .into_iter()
+            .enumerate()
+            .map(|(i, count)| {
+                let size = window.min(self.cap - i * window);
+                count as f64 / size as f64
+            })
+            .collect()
+    }
+
+    /// Returns how many keys fall in each of `bins` bins covering
+    /// `0..self.cap()`, where bin `b` holds the keys in
+    /// `b * cap / bins .. (b + 1) * cap / bins` (with floor division). The
+    /// bins differ in width by at most one, and the last ends exactly at
+    /// `cap`. Runs in `O(self.len() + bins)` time.
+    ///
+    /// # Panics
+    /// Panics if `bins` is 0.
+    pub fn histogram(&self, bins: usize) -> Vec<usize> {
+        assert!(bins > 0, "bins must be positive");
+        let mut counts = vec![0; bins];
+        for &key in self.keys() {
+            // The largest b with b * cap / bins <= key
+            let bin = ((key as u128 + 1) * bins as u128 - 1) / self.cap as u128;
+            counts[bin as usize] += 1;
+        }
+        counts
+    }
+
+    /// Returns a set with capacity `ceil(self.cap() / factor)` in which
+    /// coarse key `c` is present iff some key in
+    /// `c * factor .. (c + 1) * factor` is present in `self`, e.g. to map a
+    /// selection of pixels to the tiles containing them. Runs in
+    /// `O(self.len())` time, plus the allocation. The coarse capacity is
+    /// never larger than `self.cap()`, so this can't currently fail; it
+    /// returns a `Result` to match [`upsample`](FastSet::upsample).
+    ///
+    /// # Panics
+    /// Panics if `factor` is 0.
+    pub fn downsample(&self, factor: usize) -> Result<FastSet, LayoutError> {
+        assert!(factor > 0, "factor must be positive");
+        let mut ret = Self::new(self.cap.div_ceil(factor))?;
+        for &key in self.keys() {
+            let coarse = key / factor;
+            if unsafe { !ret.unchecked_contains(coarse) } {
+                unsafe { ret.unchecked_add(coarse) };
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Returns a set with capacity `self.cap() * factor` in which each key
+    /// `c` of `self` is expanded to all of `c * factor .. (c + 1) * factor`,
+    /// the inverse of [`downsample`](FastSet::downsample). Runs in
+    /// `O(self.len() * factor)` time, plus the allocation. Returns an error
+    /// if the new capacity overflows or is greater than `isize::MAX`.
+    pub fn upsample(&self, factor: usize) -> Result<FastSet, LayoutError> {
+        // usize::MAX is far too big anyway, so saturating keeps the error
+        let mut ret = Self::new(self.cap.saturating_mul(factor))?;
+        for &key in self.keys() {
+            for fine in key * factor..(key + 1) * factor {
+                unsafe { ret.unchecked_add(fine) };
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Returns a set with capacity `modulus` containing `k % modulus` for
+    /// each key `k`, i.e. the residues the keys cover; keys sharing a residue
+    /// collapse into one. Runs in `O(self.len())` time, plus the allocation.
+    /// Returns an error if `modulus` is greater than `isize::MAX`.
+    ///
+    /// # Panics
+    /// Panics if `modulus` is 0.
+    pub fn fold_modulo(&self, modulus: usize) -> Result<FastSet, LayoutError> {
+        assert!(modulus > 0, "modulus must be positive");
+        let mut ret = Self::new(modulus)?;
+        for &key in self.keys() {
+            let residue = key % modulus;
+            if unsafe { !ret.unchecked_contains(residue) } {
+                unsafe { ret.unchecked_add(residue) };
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Returns a set with the same capacity in which each key is rounded
+    /// down to a multiple of `grid`, i.e. `key / grid * grid`; keys snapping
+    /// to the same point collapse into one. Rounding down never leaves the
+    /// capacity, so this can't currently fail; it returns a `Result` to
+    /// match [`downsample`](FastSet::downsample). Runs in `O(self.len())`
+    /// time.
+    ///
+    /// # Panics
+    /// Panics if `grid` is 0.
+    pub fn snap_to_grid(&self, grid: usize) -> Result<FastSet, LayoutError> {
+        assert!(grid > 0, "grid must be positive");
+        let mut ret = self.empty_like();
+        for &key in self.keys() {
+            let snapped = key / grid * grid;
+            if unsafe { !ret.unchecked_contains(snapped) } {
+                unsafe { ret.unchecked_add(snapped) };
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Returns a set with the same capacity in which each key `k` becomes
+    /// `k + offset`. Shifted keys that fall outside `0..self.cap()` are
+    /// silently dropped, so no key is ever out of bounds and this currently
+    /// always returns `Ok`. Runs in `O(self.len())` time.
+    pub fn shifted(&self, offset: isize) -> Result<FastSet, OutOfBounds> {
+        let mut ret = self.empty_like();
+        for &key in self.keys() {
+            match key.checked_add_signed(offset) {
+                Some(moved) if moved < self.cap => unsafe { ret.unchecked_add(moved) },
+                _ => {}
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Returns a set with the same capacity in which each key `k` becomes its
+    /// mirror image `2 * center - k`. Reflections outside `0..self.cap()`
+    /// are silently dropped, so reflecting twice about the same center gives
+    /// back exactly the keys whose reflections were in range.
+    /// Runs in `O(self.len())` time.
+    pub fn reflect(&self, center: usize) -> FastSet {
+        let mut ret = self.empty_like();
+        for &key in self.keys() {
+            if let Some(mirror) = Self::mirror(key, center).filter(|&mirror| mirror < self.cap) {
+                unsafe { ret.unchecked_add(mirror) };
+            }
+        }
+        ret
+    }
+
+    /// Checks whether the set is its own mirror image about `center`: for
+    /// every key `k`, `2 * center - k` is also present. A key whose
+    /// reflection falls outside `0..self.cap()` has no partner, so the
+    /// result is then `false`. The empty set is symmetric about any center.
+    /// Runs in `O(self.len())` time, stopping at the first asymmetry.
+    pub fn is_symmetric_about(&self, center: usize) -> bool {
+        self.keys()
+            .iter()
+            .all(|&key| Self::mirror(key, center).is_some_and(|mirror| self.contains(mirror)))
+    }
+
+    /// Returns `2 * center - key`, or `None` if that's negative or too large
+    /// for a `usize` (and so too large to be a key anyway).
+    fn mirror(key: usize, center: usize) -> Option<usize> {
+        center.checked_mul(2)?.checked_sub(key)
+    }
+
+    /// Returns `Some(offset)` if `other` holds exactly the keys of `self`
+    /// shifted by `offset`, i.e. the two are translates of each other, and
+    /// `None` otherwise. The only candidate is the difference of their
+    /// minimums, so that's the one checked. Two empty sets give `Some(0)`.
+    /// Capacities don't matter. Runs in `O(self.len())` time.
+    pub fn equal_under_shift(&self, other: &FastSet) -> Option<isize> {
+        if self.len != other.len {
+            return None;
+        }
+        let (Some(ours), Some(theirs)) = (self.min(), other.min()) else {
+            return Some(0);
+        };
+        // Keys are less than isize::MAX, so this can't overflow
+        let offset = theirs as isize - ours as isize;
+        self.keys()
+            .iter()
+            .all(|&key| other.contains(key.wrapping_add_signed(offset)))
+            .then_some(offset)
+    }
+
+    /// Returns how many keys `k` have `k + lag` in the set too, i.e. the
+    /// size of the overlap between the set and itself shifted by `lag`. A
+    /// periodic set peaks at multiples of its period. `lag == 0` gives
+    /// `self.len()`. Runs in `O(self.len())` time.
+    pub fn self_overlap(&self, lag: usize) -> usize {
+        self.keys()
+            .iter()
+            .filter(|&&key| {
+                key.checked_add(lag)
+                    .is_some_and(|moved| self.contains(moved))
+            })
+            .count()
+    }
+
+    /// Checks whether the set is closed under adding `step`: for every key
+    /// `k`, `k + step` is also present, except that successors `>= self.cap()`
+    /// (which the set couldn't hold) aren't required. So a closed nonempty
+    /// set with `step > 0` contains every `step`th key from its minimum up to
+    /// the end of the capacity. Runs in `O(self.len())` time, stopping at the
+    /// first violation.
+    pub fn is_successor_closed(&self, step: usize) -> bool {
+        self.keys().iter().all(|&key| match key.checked_add(step) {
+            Some(next) if next < self.cap => unsafe { self.unchecked_contains(next) },
+            _ => true,
+        })
+    }
+
+    /// Returns the morphological dilation of the set by `offsets`: a set
+    /// with capacity `cap` containing `key + offset` for every key and
+    /// offset. Sums that are `>= cap` are silently dropped, so this currently
+    /// always returns `Ok`. Runs in `O(self.len() * offsets.len())` time.
+    ///
+    /// # Panics
+    /// Panics if `cap` is greater than `isize::MAX`.
+    pub fn dilate(&self, offsets: &[usize], cap: usize) -> Result<FastSet, OutOfBounds> {
+        let mut ret = Self::new(cap).unwrap();
+        for &key in self {
+            for &offset in offsets {
+                // Saturating is fine, since usize::MAX is never a valid key
+                let sum = key.saturating_add(offset);
+                if ret.would_insert(sum) {
+                    unsafe { ret.unchecked_add(sum) };
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Returns the morphological erosion of the set by `offsets`: a set with
+    /// the same capacity keeping each key `k` for which every `k + offset`
+    /// is present. Sums that are `>= self.cap()` count as absent, so keys
+    /// near the end of the capacity are eroded away by any positive offset.
+    /// With no offsets every key is kept. Runs in
+    /// `O(self.len() * offsets.len())` time.
+    pub fn erode(&self, offsets: &[usize]) -> FastSet {
+        self.filter(|key| {
+            offsets.iter().all(|&offset| {
+                key.checked_add(offset)
+                    .is_some_and(|sum| self.contains(sum))
+            })
+        })
+    }
+
+    /// Returns an iterator over alternating runs of present and absent keys,
+    /// as `(present, range)` pairs in ascending order. The ranges are
+    /// non-empty and exactly tile `0..self.cap()`. Setting up the iterator
+    /// takes `O(self.len() * log(self.len()))` time.
+    pub fn runs_iter(&self) -> impl Iterator<Item = (bool, Range<usize>)> + '_ {
+        let mut all = Vec::new();
+        let mut next = 0;
+        for run in self.to_runs() {
+            if run.start > next {
+                all.push((false, next..run.start));
+            }
+            next = run.end;
+            all.push((true, run));
+        }
+        if next < self.cap {
+            all.push((false, next..self.cap));
+        }
+        all.into_iter()
+    }
+
+    /// Returns an iterator over the keys in ascending order as
+    /// `(key, is_run_start, is_run_end)`, where the flags say whether `key`
+    /// begins or ends a maximal run of consecutive keys. A key on its own is
+    /// both. Setting up the iterator takes `O(self.len() * log(self.len()))`
+    /// time.
+    pub fn iter_sorted_with_boundaries(&self) -> impl Iterator<Item = (usize, bool, bool)> {
+        let sorted = self.to_sorted_vec();
+        (0..sorted.len()).map(move |i| {
+            let key = sorted[i];
+            let start = i == 0 || sorted[i - 1] + 1 != key;
+            let end = i + 1 == sorted.len() || sorted[i + 1] != key + 1;
+            (key, start, end)
+        })
+    }
+
+    /// Returns the number of maximal runs of absent keys in `0..self.cap()`.
+    /// An empty set has one gap (unless `self.cap() == 0`, with none), and a
+    /// full set has none. Runs in `O(self.len() * log(self.len()))` time.
+    pub fn gap_count(&self) -> usize {
+        self.runs_iter().filter(|(present, _)| !present).count()
+    }
+
+    /// Returns `(start, len)` for the longest run of absent keys in
+    /// `0..self.cap()`, the earliest one if there's a tie, or `None` if there
+    /// are no absent keys. An empty set gives `Some((0, self.cap()))`.
+    /// Runs in `O(self.len() * log(self.len()))` time.
+    pub fn largest_gap(&self) -> Option<(usize, usize)> {
+        self.runs_iter()
+            .filter(|(present, _)| !present)
+            .map(|(_, gap)| (gap.start, gap.len()))
+            .fold(None, |best, gap| match best {
+                Some((_, len)) if len >= gap.1 => best,
+                _ => Some(gap),
+            })
+    }
+
+    /// Filters `iter` down to the keys not yet in the set, adding each one as
+    /// it goes, so that only the first occurrence of each key is returned.
+    /// Keys already in the set beforehand count as seen. Keys `>= self.cap()`
+    /// can't be tracked, so they are skipped.
+    pub fn dedup_first<I: IntoIterator<Item = usize>>(&mut self, iter: I) -> Vec<usize> {
+        let mut ret = Vec::new();
+        for key in iter {
+            if key < self.cap && unsafe { !self.unchecked_contains(key) } {
+                unsafe { self.unchecked_add(key) };
+                ret.push(key);
+            }
+        }
+        ret
+    }
+
+    /// Returns `Some(step)` if the keys, in ascending order, form an arithmetic
+    /// progression with common difference `step`, and `None` otherwise. Sets
+    /// with fewer than two keys (including the empty set) trivially qualify
+    /// and return `Some(0)`, a step no set of two or more keys can have.
+    /// Runs in `O(self.len() * log(self.len()))` time.
+    pub fn is_arithmetic(&self) -> Option<usize> {
+        let keys = self.to_sorted_vec();
+        if keys.len() < 2 {
+            return Some(0);
+        }
+        let step = keys[1] - keys[0];
+        keys.windows(2)
+            .all(|pair| pair[1] - pair[0] == step)
+            .then_some(step)
+    }
+
+    /// Checks whether `label` is non-decreasing over the keys in ascending
+    /// order, i.e. `label(a) <= label(b)` whenever `a < b`. Equal labels are
+    /// allowed; for a strict order, check that the labels are distinct too.
+    /// Sets with fewer than two keys are trivially monotone. Runs in
+    /// `O(self.len() * log(self.len()))` time, calling `label` once per key.
+    pub fn is_monotone_under<F: Fn(usize) -> usize>(&self, label: F) -> bool {
+        let labels: Vec<usize> = self.to_sorted_vec().into_iter().map(label).collect();
+        labels.windows(2).all(|pair| pair[0] <= pair[1])
+    }
+
+    /// Returns an iterator over `key * elem_size` for each key, in the same
+    /// order as [`keys`](FastSet::keys): the byte offsets of the selected
+    /// elements in a packed buffer of `elem_size`-byte elements.
+    ///
+    /// # Panics
+    /// The iterator panics if an offset overflows `usize`.
+    pub fn byte_offsets(&self, elem_size: usize) -> impl Iterator<Item = usize> + '_ {
+        self.keys().iter().map(move |&key| {
+            key.checked_mul(elem_size)
+                .expect("byte offset overflows usize")
+        })
+    }
+
+    /// Returns the arithmetic mean of the keys, or `None` if the set is empty.
+    /// The keys are summed exactly as a `u128`, which can't overflow, before a
+    /// single division. Runs in `O(self.len())` time.
+    pub fn mean(&self) -> Option<f64> {
+        if self.is_empty() {
+            return None;
+        }
+        let sum: u128 = self.keys().iter().map(|&key| key as u128).sum();
+        Some(sum as f64 / self.len as f64)
+    }
+
+    /// Returns the mean of the keys weighted by `weights`, i.e.
+    /// `sum(k * weights[k]) / sum(weights[k])` over the keys `k`, like a
+    /// center of mass. Returns `None` if the set is empty or the total weight
+    /// of its keys is zero, since the mean is then undefined.
+    /// Runs in `O(self.len())` time.
+    ///
+    /// # Panics
+    /// Panics if `weights.len() < self.cap()`.
+    pub fn weighted_mean(&self, weights: &[f64]) -> Option<f64> {
+        assert!(weights.len() >= self.cap, "need a weight for every key");
+        let (mut moment, mut total) = (0.0, 0.0);
+        for &key in self.keys() {
+            moment += key as f64 * weights[key];
+            total += weights[key];
+        }
+        if total == 0.0 {
+            return None;
+        }
+        Some(moment / total)
+    }
+
+    /// Checks whether every key of `self` is also in `other`. Usually this
+    /// probes `other` for each key, in `O(self.len())` time. But probing
+    /// jumps around memory, so once the arrays are too big for the cache it
+    /// loses to [`is_subset_dense`](FastSet::is_subset_dense), which streams
+    /// through both sets instead. Going by `cargo bench`, that's worthwhile
+    /// when `self.cap() >= 2^19` and `self.len() >= other.len() / 2`.
+    pub fn is_subset(&self, other: &FastSet) -> bool {
+        const MIN_DENSE_CAP: usize = 1 << 19;
+        if self.len > other.len {
+            return false;
+        }
+        if self.cap >= MIN_DENSE_CAP && self.len >= other.len / 2 {
+            self.is_subset_dense(other)
+        } else {
+            self.keys().iter().all(|&key| other.contains(key))
+        }
+    }
+
+    /// Checks whether the two sets have the same capacity and the same keys,
+    /// in any order. The physical allocations
+    /// ([`sparse_capacity`](FastSet::sparse_capacity) and
+    /// [`backref_capacity`](FastSet::backref_capacity)) are ignored; today
+    /// they always equal `cap`, but this stays correct if that changes. To
+    /// compare keys regardless of capacity, check that the lengths match and
+    /// one [`is_subset`](FastSet::is_subset) of the other.
+    /// Runs in `O(self.len())` time.
+    pub fn logically_equal(&self, other: &FastSet) -> bool {
+        self.cap == other.cap
+            && self.len == other.len
+            && self
+                .keys()
+                .iter()
+                .all(|&key| unsafe { other.unchecked_contains(key) })
+    }
+
+    /// Checks whether every key of `self` is also in `other`, by building a
+    /// bitmap of each set over `0..self.cap()` and checking `a & !b == 0` a
+    /// word at a time. This takes `O(self.len() + other.len() + self.cap() / 64)`
+    /// time and `self.cap() / 4` bytes of scratch memory. Prefer
+    /// [`is_subset`](FastSet::is_subset), which picks this or probing.
+    pub fn is_subset_dense(&self, other: &FastSet) -> bool {
+        if self.len > other.len {
+            return false;
+        }
+        let a = self.bitmap(self.cap);
+        let b = other.bitmap(self.cap);
+        a.iter().zip(&b).all(|(a, b)| a & !b == 0)
+    }
+
+    /// Returns the keys less than `cap` as a bitmap of `ceil(cap / 64)` words,
+    /// with key `k` stored as bit `k % 64` of word `k / 64`.
+    fn bitmap(&self, cap: usize) -> Vec<u64> {
+        let mut words = vec![0u64; cap.div_ceil(64)];
+        for &key in self {
+            if key < cap {
+                words[key / 64] |= 1 << (key % 64);
+            }
+        }
+        words
+    }
+
+    /// Returns the smallest key in the set, or `None` if it is empty.
+    /// Runs in `O(self.len())` time.
+    pub fn min(&self) -> Option<usize> {
+        self.keys().iter().copied().min()
+    }
+
+    /// Returns the largest key in the set, or `None` if it is empty.
+    /// Runs in `O(self.len())` time.
+    pub fn max(&self) -> Option<usize> {
+        self.keys().iter().copied().max()
+    }
+
+    /// Returns the smallest capacity that could hold every key in the set,
+    /// i.e. `self.max() + 1`, or 0 if the set is empty. This is the capacity
+    /// that [`shrink_to_fit`](FastSet::shrink_to_fit) leaves the set with.
+    /// Runs in `O(self.len())` time.
+    pub fn min_cap(&self) -> usize {
+        self.max().map_or(0, |max| max + 1)
+    }
+
+    /// Returns the `n`th smallest key (counting from 0), or `None` if
+    /// `n >= self.len()`. This copies the keys and runs a partial selection,
+    /// taking `O(self.len())` expected time per call; for many queries, sort
+    /// once with [`sorted_cached`](FastSet::sorted_cached) and index that
+    /// instead.
+    pub fn select(&self, n: usize) -> Option<usize> {
+        if n >= self.len {
+            return None;
+        }
+        let mut keys = self.keys().to_vec();
+        Some(*keys.select_nth_unstable(n).1)
+    }
+
+    /// Reduces the capacity to [`min_cap`](FastSet::min_cap), keeping every
+    /// key, and gives the allocator the chance to shrink in place. An empty
+    /// set ends up with capacity 0. Runs in `O(self.len())` time, plus the
+    /// cost of reallocating.
+    pub fn shrink_to_fit(&mut self) {
+        // Can't fail, since min_cap <= cap
+        self.resize_preserving(self.min_cap()).unwrap();
+    }
+
+    /// Like [`shrink_to_fit`](FastSet::shrink_to_fit), but only if the
+    /// fraction of the capacity that would be freed,
+    /// `(cap - min_cap) / cap`, is greater than `max_waste_ratio`. That way a
+    /// long-lived set reclaims memory when it is badly over-provisioned
+    /// without reallocating after every removal. Does nothing if the capacity
+    /// is already 0. Runs in `O(self.len())` time, plus the cost of
+    /// reallocating if it shrinks.
+    pub fn shrink_if_sparse(&mut self, max_waste_ratio: f64) {
+        if self.cap == 0 {
+            return;
+        }
+        let min_cap = self.min_cap();
+        if (self.cap - min_cap) as f64 / self.cap as f64 > max_waste_ratio {
+            // Can't fail, since min_cap <= cap
+            self.resize_preserving(min_cap).unwrap();
+        }
+    }
+
+    /// Calls [`shrink_to_fit`](FastSet::shrink_to_fit) on each of `sets`, to
+    /// reclaim memory across a pool during idle periods. This changes their
+    /// allocations and capacities, but not which keys they hold. Runs in time
+    /// proportional to the total length of `sets`, plus the cost of
+    /// reallocating.
+    pub fn compact_pool(sets: &mut [FastSet]) {
+        for set in sets {
+            set.shrink_to_fit();
+        }
+    }
+
+    /// Returns `Some(min..max + 1)` if the set is exactly the keys in that
+    /// range, with no gaps, and `None` otherwise (including if it is empty).
+    /// Runs in `O(self.len())` time.
+    pub fn as_contiguous(&self) -> Option<Range<usize>> {
+        let (min, max) = (self.min()?, self.max()?);
+        // The keys are distinct and within min..=max, so there's room for no gaps
+        (max - min + 1 == self.len).then_some(min..max + 1)
+    }
+
+    /// Returns `Some(0..self.len())` if the set is exactly the keys in that
+    /// range, and `None` otherwise. An empty set gives `Some(0..0)`.
+    /// Runs in `O(self.len())` time.
+    pub fn try_as_range(&self) -> Option<Range<usize>> {
+        // The keys are distinct, so if none exceeds len - 1 they fill 0..len
+        match self.max() {
+            Some(max) if max + 1 != self.len => None,
+            _ => Some(0..self.len),
+        }
+    }
+
+    /// Checks whether the set is exactly the keys in `range`. Once the lengths
+    /// are known to match, it's enough to check that every key lies in
+    /// `range`, which reads the keys in order rather than probing, so this
+    /// runs in `O(self.len())` time.
+    pub fn is_run(&self, range: Range<usize>) -> bool {
+        self.len == range.len() && self.keys().iter().all(|key| range.contains(key))
+    }
+
+    /// Returns an iterator over the differences between consecutive keys, in
+    /// ascending key order (not insertion order): `keys[i + 1] - keys[i]` for
+    /// the sorted keys. Yields nothing for sets with fewer than two keys.
+    /// Setting up the iterator takes `O(self.len() * log(self.len()))` time.
+    pub fn adjacent_gaps(&self) -> impl Iterator<Item = usize> + '_ {
+        let keys = self.to_sorted_vec();
+        (1..keys.len()).map(move |i| keys[i] - keys[i - 1])
+    }
+
+    /// Returns the differences between consecutive keys in ascending order,
+    /// like [`adjacent_gaps`](FastSet::adjacent_gaps) but as a `Vec`. There
+    /// are `self.len() - 1` gaps, or none if the set has fewer than two keys.
+    /// Runs in `O(self.len() * log(self.len()))` time.
+    pub fn gap_sequence(&self) -> Vec<usize> {
+        self.adjacent_gaps().collect()
+    }
+
+    /// Returns the number of clusters of keys, where two keys at most
+    /// `max_gap` apart are in the same cluster. With `max_gap == 1` this is
+    /// the number of runs in [`to_runs`](FastSet::to_runs); larger values
+    /// merge nearby runs, and `max_gap == 0` puts every key on its own. An
+    /// empty set has no clusters. Runs in `O(self.len() * log(self.len()))`
+    /// time.
+    pub fn component_count(&self, max_gap: usize) -> usize {
+        if self.is_empty() {
+            return 0;
+        }
+        1 + self.adjacent_gaps().filter(|&gap| gap > max_gap).count()
+    }
+
+    /// Returns the cluster containing `key`, as in
+    /// [`component_count`](FastSet::component_count), as a new set with the
+    /// same capacity, or `None` if `key` isn't present. Runs in
+    /// `O(self.len() * log(self.len()))` time.
+    pub fn component_of(&self, key: usize, max_gap: usize) -> Option<FastSet> {
+        if !self.contains(key) {
+            return None;
+        }
+        let sorted = self.to_sorted_vec();
+        let i = sorted.binary_search(&key).unwrap();
+        let mut start = i;
+        while start > 0 && sorted[start] - sorted[start - 1] <= max_gap {
+            start -= 1;
+        }
+        let mut end = i + 1;
+        while end < sorted.len() && sorted[end] - sorted[end - 1] <= max_gap {
+            end += 1;
+        }
+        let mut ret = self.empty_like();
+        for &key in &sorted[start..end] {
+            unsafe { ret.unchecked_add(key) };
+        }
+        Some(ret)
+    }
+
+    /// Returns `(to_add, to_remove)`: the keys to add to and remove from
+    /// `self` to turn it into `target`, i.e. `target - self` and
+    /// `self - target`, each in arbitrary order.
+    /// Runs in `O(self.len() + target.len())` time.
+    pub fn edit_script(&self, target: &FastSet) -> (Vec<usize>, Vec<usize>) {
+        let to_add = target
+            .keys()
+            .iter()
+            .copied()
+            .filter(|&key| !self.contains(key));
+        let to_remove = self
+            .keys()
+            .iter()
+            .copied()
+            .filter(|&key| !target.contains(key));
+        (to_add.collect(), to_remove.collect())
+    }
+
+    /// Removes every key in `to_remove`, then adds every key in `to_add`, so
+    /// that applying [`edit_script`](FastSet::edit_script)'s output turns a
+    /// set into the target. Returns an `OutOfBounds` at the first key that is
+    /// `>= self.cap()`; the edits before it have already been applied.
+    pub fn apply_edits(
+        &mut self,
+        to_add: &[usize],
+        to_remove: &[usize],
+    ) -> Result<(), OutOfBounds> {
+        for &key in to_remove {
+            self.remove(key)?;
+        }
+        for &key in to_add {
+            self.add(key)?;
+        }
+        Ok(())
+    }
+
+    /// Returns `(added, removed)`: the keys added to and removed from `base`
+    /// to get `self`, each in ascending order, for storing `self` as a delta
+    /// against `base`. [`apply_delta`](FastSet::apply_delta) reverses this.
+    /// Runs in `O(n * log(n))` time, where `n = self.len() + base.len()`.
+    pub fn delta_from(&self, base: &FastSet) -> (Vec<usize>, Vec<usize>) {
+        let (mut added, mut removed) = base.edit_script(self);
+        added.sort_unstable();
+        removed.sort_unstable();
+        (added, removed)
+    }
+
+    /// Applies a delta from [`delta_from`](FastSet::delta_from), removing the
+    /// keys in `removed` and then adding those in `added`, just like
+    /// [`apply_edits`](FastSet::apply_edits).
+    pub fn apply_delta(&mut self, added: &[usize], removed: &[usize]) -> Result<(), OutOfBounds> {
+        self.apply_edits(added, removed)
+    }
+
+    /// Consumes the set, returning a [`SortedSet`] of its keys, for when the
+    /// set is finished being built but will be queried in order many times.
+    /// Runs in `O(self.len() * log(self.len()))` time.
+    pub fn into_sorted_frozen(self) -> SortedSet {
+        SortedSet {
+            keys: self.to_sorted_vec().into_boxed_slice(),
+        }
+    }
+
+    /// Returns one digest per `chunk`-sized block of `0..self.cap()`, so
+    /// `ceil(self.cap() / chunk)` in all, for comparing sets block by block.
+    /// The digest of a block is the wrapping `u64` sum of `splitmix64(k)` over
+    /// its keys `k`, using the SplitMix64 finalizer; it doesn't depend on the
+    /// order keys were added, and an empty block's digest is 0.
+    /// Runs in `O(self.len() + self.cap() / chunk)` time.
+    ///
+    /// # Panics
+    /// Panics if `chunk` is 0.
+    pub fn range_digests(&self, chunk: usize) -> Vec<u64> {
+        assert!(chunk > 0, "chunk size must be nonzero");
+        let mut digests = vec![0u64; self.cap.div_ceil(chunk)];
+        for &key in self {
+            let digest = &mut digests[key / chunk];
+            *digest = digest.wrapping_add(mix64(key as u64));
+        }
+        digests
+    }
+
+    /// Returns an order-independent checksum of the keys: the XOR of
+    /// `splitmix64(k)` over every key `k`, using the SplitMix64 finalizer, or
+    /// 0 for an empty set. Since adding or removing `k` XORs the checksum with
+    /// `splitmix64(k)`, it is easy to maintain alongside a stream of changes.
+    /// Runs in `O(self.len())` time.
+    pub fn checksum(&self) -> u64 {
+        self.keys()
+            .iter()
+            .fold(0, |sum, &key| sum ^ mix64(key as u64))
+    }
+
+    /// Checks whether [`checksum`](FastSet::checksum) equals `expected`.
+    /// Runs in `O(self.len())` time.
+    pub fn verify_checksum(&self, expected: u64) -> bool {
+        self.checksum() == expected
+    }
+
+    /// Returns an order-independent hash of the symmetric difference of the
+    /// two sets, i.e. of the change between them, so that equal changes hash
+    /// equally and `a.diff_hash(&b) == b.diff_hash(&a)`. It's the XOR of
+    /// `splitmix64(k)` over every key `k` in exactly one of the sets, found
+    /// using [`tagged_union`](FastSet::tagged_union) without allocating, so
+    /// it also equals `self.checksum() ^ other.checksum()`. It's 0 if the
+    /// sets have the same keys. Runs in `O(self.len() + other.len())` time.
+    pub fn diff_hash(&self, other: &FastSet) -> u64 {
+        self.tagged_union(other)
+            .filter(|&(_, source)| source != Source::Both)
+            .fold(0, |hash, (key, _)| hash ^ mix64(key as u64))
+    }
+
+    /// Returns a string that depends only on which keys are present, not on
+    /// insertion order or capacity, for use as a cache key. The keys are
+    /// sorted and delta-encoded (the first key, then each difference from
+    /// the previous key), each delta is written as an unsigned LEB128
+    /// varint, and the bytes are formatted as lowercase hex. The empty set
+    /// gives the empty string. This format is stable across runs and
+    /// versions. Runs in `O(self.len() * log(self.len()))` time.
+    pub fn canonical_key(&self) -> String {
+        let mut bytes = Vec::new();
+        let mut prev = 0;
+        for key in self.to_sorted_vec() {
+            write_varint(&mut bytes, key - prev);
+            prev = key;
+        }
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// Encodes the set compactly as bytes: `cap` and `len`, then the keys in
+    /// ascending order as the first key followed by each difference from the
+    /// previous key, all as unsigned LEB128 varints (as for
+    /// [`canonical_key`](FastSet::canonical_key)). Gaps under 128 take one
+    /// byte, so dense or clustered sets encode far more compactly than a
+    /// list of fixed-width keys. Decode with
+    /// [`from_delta_varint`](FastSet::from_delta_varint). Runs in
+    /// `O(self.len() * log(self.len()))` time.
+    pub fn to_delta_varint(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_varint(&mut bytes, self.cap);
+        write_varint(&mut bytes, self.len);
+        let mut prev = 0;
+        for key in self.to_sorted_vec() {
+            write_varint(&mut bytes, key - prev);
+            prev = key;
+        }
+        bytes
+    }
+
+    /// Decodes a set encoded by [`to_delta_varint`](FastSet::to_delta_varint),
+    /// with the same capacity and keys. Returns a `DecodeError`, rather than
+    /// panicking, if `bytes` is truncated or has bytes left over, or if the
+    /// keys aren't strictly increasing or don't fit in the capacity. Note
+    /// that the encoded capacity is trusted for the allocation, so
+    /// untrusted input can ask for a large (if valid) one.
+    pub fn from_delta_varint(bytes: &[u8]) -> Result<FastSet, DecodeError> {
+        let mut rest = bytes;
+        let cap = read_varint(&mut rest)?;
+        let len = read_varint(&mut rest)?;
+        // Each key takes at least one byte, so this catches bad lengths early
+        if len > rest.len() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let mut ret = Self::new(cap)?;
+        let mut prev = None;
+        for _ in 0..len {
+            let gap = read_varint(&mut rest)?;
+            let key = match prev {
+                None => gap,
+                Some(prev) if gap == 0 => return Err(DecodeError::NotIncreasing { key: prev }),
+                Some(prev) => prev.checked_add(gap).ok_or(DecodeError::Overflow)?,
+            };
+            if key >= cap {
+                return Err(OutOfBounds { cap, key }.into());
+            }
+            unsafe { ret.unchecked_add(key) };
+            prev = Some(key);
+        }
+        if !rest.is_empty() {
+            return Err(DecodeError::TrailingBytes);
+        }
+        Ok(ret)
+    }
+
+    /// Returns an iterator over the non-empty 64-key blocks of the set, in
+    /// ascending order, as `(base, mask)` pairs: `base` is a multiple of 64,
+    /// and bit `i` of `mask` is set iff `self.contains(base + i)`. Setting up
+    /// the iterator takes `O(self.len() * log(self.len()))` time.
+    pub fn block_masks(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        let mut blocks: Vec<(usize, u64)> = Vec::new();
+        for key in self.to_sorted_vec() {
+            let base = key - key % 64;
+            match blocks.last_mut() {
+                Some((last, mask)) if *last == base => *mask |= 1 << (key % 64),
+                _ => blocks.push((base, 1 << (key % 64))),
+            }
+        }
+        blocks.into_iter()
+    }
+
+    /// Returns the indices `i` for which `self.contains(keys[i])`, in
+    /// ascending order. Runs in `O(keys.len())` time.
+    pub fn present_indices(&self, keys: &[usize]) -> Vec<usize> {
+        (0..keys.len())
+            .filter(|&i| self.contains(keys[i]))
+            .collect()
+    }
+
+    /// Returns the elements of `keys` that are present in the set, in the
+    /// order they appear in `keys`. Runs in `O(keys.len())` time.
+    pub fn present_values(&self, keys: &[usize]) -> Vec<usize> {
+        keys.iter()
+            .copied()
+            .filter(|&key| self.contains(key))
+            .collect()
+    }
+
+    /// Returns an iterator over the keys in Z-order (Morton order), treating
+    /// each key as a point on a grid. With `b` the number of bits in
+    /// `self.cap() - 1` and `h = ceil(b / 2)`, key `k` is the point with
+    /// `x = k % 2^h` and `y = k / 2^h` (so a row-major grid `2^h` wide), and
+    /// points are ordered by interleaving the bits of `x` and `y`, with `x`
+    /// in the even bits. Setting up the iterator takes
+    /// `O(self.len() * log(self.len()))` time.
+    pub fn iter_morton(&self) -> impl Iterator<Item = usize> + '_ {
+        /// Moves bit `i` of the low 32 bits of `x` to bit `2 * i`.
+        fn spread(x: u64) -> u64 {
+            let mut x = x & 0xffff_ffff;
+            x = (x | x << 16) & 0x0000_ffff_0000_ffff;
+            x = (x | x << 8) & 0x00ff_00ff_00ff_00ff;
+            x = (x | x << 4) & 0x0f0f_0f0f_0f0f_0f0f;
+            x = (x | x << 2) & 0x3333_3333_3333_3333;
+            (x | x << 1) & 0x5555_5555_5555_5555
+        }
+        let bits = usize::BITS - self.cap.saturating_sub(1).leading_zeros();
+        let half = bits.div_ceil(2);
+        let mut keys = self.keys().to_vec();
+        keys.sort_unstable_by_key(|&key| {
+            let (x, y) = (key as u64 & ((1 << half) - 1), key as u64 >> half);
+            spread(x) | spread(y) << 1
+        });
+        keys.into_iter()
+    }
+
+    /// Returns a new set with the same capacity as `self`, containing the keys
+    /// for which `f` returns `true`. `self` is left unchanged.
+    /// Runs in `O(self.len())` time.
+    pub fn filter<F: FnMut(usize) -> bool>(&self, mut f: F) -> FastSet {
+        let mut ret = self.empty_like();
+        for &key in self {
+            if f(key) {
+                unsafe { ret.unchecked_add(key) };
+            }
+        }
+        ret
+    }
+
+    /// Returns a new set with capacity `new_cap` containing `f(key)` for each
+    /// key, calling `f` once per key in insertion order. If `f` maps several
+    /// keys to the same value they collapse into one. Returns an
+    /// `OutOfBounds` for the first `f(key)` that is `>= new_cap`. Runs in
+    /// `O(self.len())` time, plus the allocation.
+    ///
+    /// # Panics
+    /// Panics if `new_cap` is greater than `isize::MAX`.
+    pub fn map_keys<F: FnMut(usize) -> usize>(
+        &self,
+        new_cap: usize,
+        mut f: F,
+    ) -> Result<FastSet, OutOfBounds> {
+        let mut ret = Self::new(new_cap).unwrap();
+        for &key in self {
+            ret.add(f(key))?;
+        }
+        Ok(ret)
+    }
+
+    /// Splits the keys by `f`, returning `(matching, non_matching)`: two new
+    /// sets with the same capacity as `self`, holding the keys for which `f`
+    /// returns `true` and `false` respectively. Runs in `O(self.len())` time.
+    pub fn partition<F: FnMut(usize) -> bool>(&self, mut f: F) -> (FastSet, FastSet) {
+        let mut matching = self.empty_like();
+        let mut non_matching = self.empty_like();
+        for &key in self {
+            let dest = if f(key) {
+                &mut matching
+            } else {
+                &mut non_matching
+            };
+            unsafe { dest.unchecked_add(key) };
+        }
+        (matching, non_matching)
+    }
+
+    /// Removes every key for which `f` returns `false`. `f` is called once
+    /// per key, but since each removal moves the last key into the removed
+    /// one's place, not quite in [`keys`](FastSet::keys) order. Runs in
+    /// `O(self.len())` time without allocating.
+    pub fn retain<F: FnMut(usize) -> bool>(&mut self, mut f: F) {
+        let mut i = 0;
+        while i < self.len {
+            let key = unsafe { *self.backref.add(i) };
+            if f(key) {
+                i += 1;
+            } else {
+                // Moves the last key into position i, to be looked at next
+                unsafe { self.unchecked_remove(key) };
+            }
+        }
+    }
+
+    /// Removes every key for which `f` returns `false`, like
+    /// [`retain`](FastSet::retain), but only after calling `f` on every key,
+    /// in [`keys`](FastSet::keys) order: the keys to remove are collected
+    /// first, then removed. This costs an allocation proportional to the
+    /// number of keys removed, but `f` sees the set's original order.
+    pub fn retain_deferred<F: FnMut(usize) -> bool>(&mut self, mut f: F) {
+        let doomed: Vec<usize> = self.keys().iter().copied().filter(|&key| !f(key)).collect();
+        for key in doomed {
+            unsafe { self.unchecked_remove(key) };
+        }
+    }
+
+    /// Removes every key `base + i` with `i < 64` for which bit `i` of `mask`
+    /// is clear, leaving keys outside `base..base + 64` alone. Only keys less
+    /// than `self.cap()` are considered. Runs in `O(64)` time.
+    pub fn intersect_with_mask(&mut self, base: usize, mask: u64) {
+        let end = base.saturating_add(64).min(self.cap);
+        for key in base..end {
+            if mask & (1 << (key - base)) == 0 && unsafe { self.unchecked_contains(key) } {
+                unsafe { self.unchecked_remove(key) };
+            }
+        }
+    }
+
+    /// Checks whether the set contains the given key.
+    ///
+    /// # Safety
+    /// The key must be less than `self.cap()`.
+    pub unsafe fn unchecked_contains(&self, key: usize) -> bool {
+        // We are assuming key < cap, in particular key < isize::MAX
+        let index = *self.sparse.add(key);
+        if index >= self.len {
+            return false;
+        }
+        *self.backref.add(index) == key
+    }
+
+    /// Adds the given key to the set.
+    ///
+    /// # Safety
+    /// The key must be less than `self.cap()` and not already in the set.
+    pub unsafe fn unchecked_add(&mut self, key: usize) {
+        // Assuming key < cap and key is not already in the set
+        *self.sparse.add(key) = self.len;
+        *self.backref.add(self.len) = key;
+        self.len += 1;
+        self.max_len_seen = self.max_len_seen.max(self.len);
+        self.max_key_seen = self.max_key_seen.max(key);
+        self.sorted_valid = false;
+    }
+
+    /// Removes the given key from the set.
+    ///
+    /// # Safety
+    /// The key must be less than `self.cap()` and already in the set.
+    pub unsafe fn unchecked_remove(&mut self, key: usize) {
+        // Assuming self.contains(key) so in particular key < cap
+        let to_delete_index = *self.sparse.add(key);
+        let to_delete = self.backref.add(to_delete_index);
+        let last = self.backref.add(self.len - 1);
+        let moved_key = *last;
+        *to_delete = moved_key;
+        *self.sparse.add(moved_key) = to_delete_index;
+        self.len -= 1;
+        self.sorted_valid = false;
+    }
+}
+
+/// Shows the keys in ascending order, eliding all but the smallest
+/// [`DEBUG_MAX_KEYS`] of them for large sets.
+impl Debug for FastSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut keys = self.keys().to_vec();
+        if keys.len() > DEBUG_MAX_KEYS {
+            keys.select_nth_unstable(DEBUG_MAX_KEYS);
+            keys.truncate(DEBUG_MAX_KEYS);
+        }
+        keys.sort_unstable();
+        let mut out = f.debug_set();
+        out.entries(&keys);
+        if self.len > DEBUG_MAX_KEYS {
+            out.entry(&format_args!("… ({} total)", self.len));
+        }
+        out.finish()
+    }
+}
+
+impl Drop for FastSet {
+    fn drop(&mut self) {
+        let events = unsafe {
+            [
+                dealloc_array(self.sparse, self.cap),
+                dealloc_array(self.backref, self.cap),
+            ]
+        };
+        if !std::thread::panicking() {
+            self.report(events);
+        }
+    }
+}
+
+// The array helpers below take a `cap` for which `Layout::array::<usize>` has
+// already succeeded. Zero-sized arrays never touch the allocator. Each returns
+// the allocator call it really made, if any, for the set to report once it is
+// consistent again.
+
+/// Allocates an array of `cap` elements, zeroed if `zeroed` is set.
+fn alloc_array(cap: usize, zeroed: bool) -> (*mut usize, Option<AllocEvent>) {
+    let layout = Layout::array::<usize>(cap).unwrap();
+    if layout.size() == 0 {
+        return (NonNull::dangling().as_ptr(), None);
+    }
+    let ptr = unsafe {
+        if zeroed {
+            alloc_zeroed(layout)
+        } else {
+            alloc(layout)
+        }
+    };
+    if ptr.is_null() {
+        handle_alloc_error(layout);
+    }
+    let bytes = layout.size();
+    (ptr as *mut usize, Some(AllocEvent::Alloc { bytes }))
+}
+
+/// Frees an array returned by [`alloc_array`] or [`realloc_array`].
+unsafe fn dealloc_array(ptr: *mut usize, cap: usize) -> Option<AllocEvent> {
+    let layout = Layout::array::<usize>(cap).unwrap();
+    if layout.size() == 0 {
+        return None;
+    }
+    dealloc(ptr as *mut u8, layout);
+    let bytes = layout.size();
+    Some(AllocEvent::Dealloc { bytes })
+}
+
+/// Resizes an array from `old_cap` to `new_cap` elements, keeping the first
+/// `min(old_cap, new_cap)`. Any new elements are uninitialized.
+unsafe fn realloc_array(
+    ptr: *mut usize,
+    old_cap: usize,
+    new_cap: usize,
+) -> (*mut usize, Option<AllocEvent>) {
+    if old_cap == 0 || new_cap == 0 {
+        // At most one of these touches the allocator
+        let freed = dealloc_array(ptr, old_cap);
+        let (ptr, allocated) = alloc_array(new_cap, false);
+        return (ptr, freed.or(allocated));
+    }
+    let old_layout = Layout::array::<usize>(old_cap).unwrap();
+    let new_layout = Layout::array::<usize>(new_cap).unwrap();
+    let ptr = realloc(ptr as *mut u8, old_layout, new_layout.size());
+    if ptr.is_null() {
+        handle_alloc_error(new_layout);
+    }
+    let event = AllocEvent::Realloc {
+        old_bytes: old_layout.size(),
+        new_bytes: new_layout.size(),
+    };
+    (ptr as *mut usize, Some(event))
+}
+
+/// Iteration runs in `O(self.len())` time.
+impl<'a> IntoIterator for &'a FastSet {
+    type Item = &'a usize;
+    type IntoIter = std::slice::Iter<'a, usize>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.keys().iter()
+    }
+}
+
+impl Clone for FastSet {
+    /// Cloning a `FastSet` takes `O(self.len())` time.
+    fn clone(&self) -> Self {
+        let mut ret = Self::new(self.cap).unwrap();
+        unsafe {
+            for key in self {
+                ret.unchecked_add(*key);
+            }
+        }
+        ret
+    }
+
+    /// Gives the allocator the opportunity to be smart; avoids allocation
+    /// entirely if `self.cap() == source.cap()`.
+    fn clone_from(&mut self, source: &Self) {
+        self.recycle(source.cap).unwrap(); // Can't fail, since it didn't for source
+        self.max_len_seen = 0;
+        self.max_key_seen = 0;
+        for key in source {
+            unsafe {
+                self.unchecked_add(*key);
+            }
+        }
+    }
+}
+
+/// The SplitMix64 finalizer, used wherever keys need hashing. Its output is
+/// part of the documented format of [`FastSet::to_bloom`] and friends, so it
+/// must not change.
+fn mix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Appends `x` to `out` as an unsigned LEB128 varint: seven bits per byte,
+/// least significant first, with the high bit set on all but the last byte.
+fn write_varint(out: &mut Vec<u8>, mut x: usize) {
+    while x >= 0x80 {
+        out.push(x as u8 | 0x80);
+        x >>= 7;
+    }
+    out.push(x as u8);
+}
+
+/// Reads an unsigned LEB128 varint, as written by [`write_varint`], from the
+/// front of `bytes`, advancing it past the varint.
+fn read_varint(bytes: &mut &[u8]) -> Result<usize, DecodeError> {
+    let mut x = 0usize;
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = bytes.split_first().ok_or(DecodeError::UnexpectedEnd)?;
+        *bytes = rest;
+        let bits = (byte & 0x7f) as usize;
+        if shift >= usize::BITS || (bits << shift) >> shift != bits {
+            return Err(DecodeError::Overflow);
+        }
+        x |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Ok(x);
+        }
+        shift += 7;
+    }
+}
+
+/// A `FastMap` is a map from `usize` keys to values of type `V`, built on the
+/// same layout as [`FastSet`]: a `FastSet` of the present keys, plus a `Vec` of
+/// values in the same order as its [`keys`](FastSet::keys). Insertion, lookup,
+/// and removal run in constant time, and [`clear`](FastMap::clear) does too
+/// if `V` doesn't need dropping.
+#[derive(Clone)]
+pub struct FastMap<V> {
+    keys: FastSet,
+    values: Vec<V>,
+}
+
+impl<V> FastMap<V> {
+    /// Create a new `FastMap`, which will hold keys less than `cap`.
+    /// Allocates `O(cap)` bytes of heap memory.
+    /// Returns an error if `cap` is greater than `isize::MAX`.
+    pub fn new(cap: usize) -> Result<FastMap<V>, LayoutError> {
+        Ok(FastMap {
+            keys: FastSet::new(cap)?,
+            values: Vec::new(),
+        })
+    }
+
+    /// Returns the number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Returns `true` if the map contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Returns the capacity of the map, i.e. the lowest key that cannot be
+    /// stored.
+    pub fn cap(&self) -> usize {
+        self.keys.cap()
+    }
+
+    /// Returns the set of keys present in the map.
+    pub fn keys(&self) -> &FastSet {
+        &self.keys
+    }
+
+    /// Checks whether the map contains the given key. Will always return
+    /// `false` if `key >= self.cap()`.
+    pub fn contains_key(&self, key: usize) -> bool {
+        self.keys.contains(key)
+    }
+
+    /// Maps `key` to `val`, returning the value it previously mapped to, if
+    /// any. Returns an `OutOfBounds` if `key >= self.cap()`.
+    pub fn insert(&mut self, key: usize, val: V) -> Result<Option<V>, OutOfBounds> {
+        let index = self.keys.add_at(key)?;
+        if index == self.values.len() {
+            self.values.push(val);
+            Ok(None)
+        } else {
+            Ok(Some(std::mem::replace(&mut self.values[index], val)))
+        }
+    }
+
+    /// Returns a reference to the value `key` maps to, if any.
+    pub fn get(&self, key: usize) -> Option<&V> {
+        self.keys.position(key).map(|index| &self.values[index])
+    }
+
+    /// Returns a mutable reference to the value `key` maps to, if any.
+    pub fn get_mut(&mut self, key: usize) -> Option<&mut V> {
+        self.keys.position(key).map(|index| &mut self.values[index])
+    }
+
+    /// Removes `key` from the map, returning the value it mapped to, if any.
+    pub fn remove(&mut self, key: usize) -> Option<V> {
+        let index = self.keys.position(key)?;
+        // Both of these move the last entry into the removed one's place
+        unsafe { self.keys.unchecked_remove(key) };
+        Some(self.values.swap_remove(index))
+    }
+
+    /// Removes all entries from the map.
+    pub fn clear(&mut self) {
+        self.keys.clear();
+        self.values.clear();
+    }
+
+    /// Returns an iterator over the entries of the map, in arbitrary order.
+    /// Iteration runs in `O(self.len())` time.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &V)> + '_ {
+        self.keys.keys().iter().copied().zip(&self.values)
+    }
+}
+
+impl<V: Debug> Debug for FastMap<V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// A `SortedSet` is an immutable set of `usize`, stored as a sorted slice, as
+/// returned by [`FastSet::into_sorted_frozen`]. It uses memory proportional
+/// to its length rather than its largest key, iterates in ascending order,
+/// and answers [`contains`](SortedSet::contains) by binary search.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SortedSet {
+    keys: Box<[usize]>,
+}
+
+impl SortedSet {
+    /// Returns the length of the set, i.e. the number of items it contains.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Returns `true` if the set contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Checks whether the set contains the given key, in `O(log(self.len()))`
+    /// time.
+    pub fn contains(&self, key: usize) -> bool {
+        self.keys.binary_search(&key).is_ok()
+    }
+
+    /// Returns the keys of the set in ascending order.
+    pub fn keys(&self) -> &[usize] {
+        &self.keys
+    }
+
+    /// Returns an iterator over the keys of the set in ascending order.
+    pub fn iter(&self) -> std::slice::Iter<'_, usize> {
+        self.keys.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a SortedSet {
+    type Item = &'a usize;
+    type IntoIter = std::slice::Iter<'a, usize>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// A `SetExpr` is an unevaluated combination of [`FastSet`]s, built with
+/// [`union`](SetExpr::union), [`intersect`](SetExpr::intersect), and
+/// [`difference`](SetExpr::difference), like
+/// `SetExpr::from(&a).union(&b).intersect(&c)`. No intermediate sets are
+/// built: [`contains`](SetExpr::contains) answers for one key in time
+/// proportional to the size of the expression, and
+/// [`evaluate`](SetExpr::evaluate) builds only the final set.
+#[derive(Clone, Debug)]
+pub enum SetExpr<'a> {
+    /// The keys of a single set.
+    Set(&'a FastSet),
+    /// The keys in either operand.
+    Union(Box<SetExpr<'a>>, Box<SetExpr<'a>>),
+    /// The keys in both operands.
+    Intersect(Box<SetExpr<'a>>, Box<SetExpr<'a>>),
+    /// The keys in the first operand but not the second.
+    Difference(Box<SetExpr<'a>>, Box<SetExpr<'a>>),
+}
+
+impl<'a> From<&'a FastSet> for SetExpr<'a> {
+    fn from(set: &'a FastSet) -> Self {
+        SetExpr::Set(set)
+    }
+}
+
+impl<'a> SetExpr<'a> {
+    /// Returns the expression `self ∪ other`.
+    pub fn union(self, other: &'a FastSet) -> Self {
+        SetExpr::Union(Box::new(self), Box::new(other.into()))
+    }
+
+    /// Returns the expression `self ∩ other`.
+    pub fn intersect(self, other: &'a FastSet) -> Self {
+        SetExpr::Intersect(Box::new(self), Box::new(other.into()))
+    }
+
+    /// Returns the expression `self - other`.
+    pub fn difference(self, other: &'a FastSet) -> Self {
+        SetExpr::Difference(Box::new(self), Box::new(other.into()))
+    }
+
+    /// Checks whether the expression's result would contain `key`, without
+    /// evaluating it. Runs in time proportional to the number of sets in the
+    /// expression.
+    pub fn contains(&self, key: usize) -> bool {
+        match self {
+            SetExpr::Set(set) => set.contains(key),
+            SetExpr::Union(a, b) => a.contains(key) || b.contains(key),
+            SetExpr::Intersect(a, b) => a.contains(key) && b.contains(key),
+            SetExpr::Difference(a, b) => a.contains(key) && !b.contains(key),
+        }
+    }
+
+    /// Returns a capacity large enough for any key in the result: the
+    /// largest capacity for a union, the smallest for an intersection, and
+    /// the first operand's for a difference.
+    pub fn cap(&self) -> usize {
+        match self {
+            SetExpr::Set(set) => set.cap(),
+            SetExpr::Union(a, b) => a.cap().max(b.cap()),
+            SetExpr::Intersect(a, b) => a.cap().min(b.cap()),
+            SetExpr::Difference(a, _) => a.cap(),
+        }
+    }
+
+    /// Builds the result as a set with capacity [`cap`](SetExpr::cap). Each
+    /// key that could be in the result (from the sets a union or the first
+    /// operand of an intersection or difference could draw on) is checked
+    /// once with [`contains`](SetExpr::contains).
+    pub fn evaluate(&self) -> FastSet {
+        // Can't fail, since the capacity is one of the operands'
+        let mut ret = FastSet::new(self.cap()).unwrap();
+        self.for_each_candidate(&mut |key| {
+            if key < ret.cap && unsafe { !ret.unchecked_contains(key) } && self.contains(key) {
+                unsafe { ret.unchecked_add(key) };
+            }
+        });
+        ret
+    }
+
+    /// Calls `f` on every key that might be in the result, possibly more
+    /// than once.
+    fn for_each_candidate(&self, f: &mut dyn FnMut(usize)) {
+        match self {
+            SetExpr::Set(set) => set.keys().iter().for_each(|&key| f(key)),
+            SetExpr::Union(a, b) => {
+                a.for_each_candidate(f);
+                b.for_each_candidate(f);
+            }
+            SetExpr::Intersect(a, _) | SetExpr::Difference(a, _) => a.for_each_candidate(f),
+        }
+    }
+}
+
+/// A `UnionAccumulator` builds the union of a stream of [`FastSet`]s one set
+/// at a time: each [`add_set`](UnionAccumulator::add_set) only touches the
+/// new set's keys, rather than rescanning all the sets so far. The capacity
+/// grows as needed to fit each set.
+#[derive(Clone, Debug)]
+pub struct UnionAccumulator {
+    union: FastSet,
+}
+
+impl UnionAccumulator {
+    /// Create a new, empty accumulator with initial capacity `cap`.
+    /// Returns an error if `cap` is greater than `isize::MAX`.
+    pub fn new(cap: usize) -> Result<UnionAccumulator, LayoutError> {
+        Ok(UnionAccumulator {
+            union: FastSet::new(cap)?,
+        })
+    }
+
+    /// Adds every key of `set` to the union, first growing the capacity to
+    /// `set.cap()` if that's larger. Runs in `O(set.len())` time, plus the
+    /// cost of growing.
+    pub fn add_set(&mut self, set: &FastSet) {
+        if set.cap > self.union.cap {
+            // Can't fail, since the layout was valid for set
+            self.union.resize_preserving(set.cap).unwrap();
+        }
+        for &key in set {
+            if unsafe { !self.union.unchecked_contains(key) } {
+                unsafe { self.union.unchecked_add(key) };
+            }
+        }
+    }
+
+    /// Returns the union of every set added so far, with capacity the
+    /// largest of theirs and the initial capacity.
+    pub fn finish(self) -> FastSet {
+        self.union
+    }
+}
+
+/// A `RecordingSet` is a [`FastSet`] that logs every change to its keys as a
+/// [`SetCommand`], for event sourcing or debugging; collect them with
+/// [`take_log`](RecordingSet::take_log). Each key actually added or removed is
+/// logged as `Add` or `Remove` (so a toggle shows up as one of those), and
+/// clearing as `Clear`. Read the set through [`set`](RecordingSet::set), or
+/// take it back with [`into_inner`](RecordingSet::into_inner).
+#[derive(Clone, Debug)]
+pub struct RecordingSet {
+    set: FastSet,
+    log: Vec<SetCommand>,
+}
+
+impl RecordingSet {
+    /// Create a new, empty `RecordingSet`, which will hold values less than
+    /// `cap`. Returns an error if `cap` is greater than `isize::MAX`.
+    pub fn new(cap: usize) -> Result<RecordingSet, LayoutError> {
+        Ok(RecordingSet {
+            set: FastSet::new(cap)?,
+            log: Vec::new(),
+        })
+    }
+
+    /// Returns the underlying set.
+    pub fn set(&self) -> &FastSet {
+        &self.set
+    }
+
+    /// Returns the underlying set, discarding any unread log.
+    pub fn into_inner(self) -> FastSet {
+        self.set
+    }
+
+    /// Adds the given key to the set, logging it if it was absent.
+    /// Returns an `OutOfBounds` if `key >= self.set().cap()`.
+    pub fn add(&mut self, key: usize) -> Result<(), OutOfBounds> {
+        if self.set.add_reporting(key)? {
+            self.log.push(SetCommand::Add(key));
+        }
+        Ok(())
+    }
+
+    /// Removes the given key from the set, logging it if it was present.
+    /// Returns an `OutOfBounds` if `key >= self.set().cap()`.
+    pub fn remove(&mut self, key: usize) -> Result<(), OutOfBounds> {
+        if self.set.remove_reporting(key)? {
+            self.log.push(SetCommand::Remove(key));
+        }
+        Ok(())
+    }
+
+    /// Adds the given key if it is absent, and removes it if it is present,
+    /// logging whichever happened. Returns an `OutOfBounds` if
+    /// `key >= self.set().cap()`.
+    pub fn toggle(&mut self, key: usize) -> Result<(), OutOfBounds> {
+        if self.set.contains(key) {
+            self.remove(key)
+        } else {
+            self.add(key)
+        }
+    }
+
+    /// Toggles each key yielded by `iter`, in order. Returns an `OutOfBounds`
+    /// at the first key that is `>= self.set().cap()`; the keys before it have
+    /// already been toggled and logged.
+    pub fn toggle_all<I: IntoIterator<Item = usize>>(
+        &mut self,
+        iter: I,
+    ) -> Result<(), OutOfBounds> {
+        for key in iter {
+            self.toggle(key)?;
+        }
+        Ok(())
+    }
+
+    /// Removes and logs every key for which `f` returns `false`.
+    pub fn retain<F: FnMut(usize) -> bool>(&mut self, mut f: F) {
+        let log = &mut self.log;
+        self.set.retain(|key| {
+            let keep = f(key);
+            if !keep {
+                log.push(SetCommand::Remove(key));
+            }
+            keep
+        });
+    }
+
+    /// Removes all elements from the set, logging a `Clear`.
+    pub fn clear(&mut self) {
+        self.set.clear();
+        self.log.push(SetCommand::Clear);
+    }
+
+    /// Returns the changes logged since the last call and starts a new, empty
+    /// log. Replaying the result with
+    /// [`apply_commands`](FastSet::apply_commands) on a set with the contents
+    /// this one had before reproduces its current contents.
+    pub fn take_log(&mut self) -> Vec<SetCommand> {
+        std::mem::take(&mut self.log)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
     fn clone() {
         let mut set1 = FastSet::new(10).unwrap();
         set1.add(3).unwrap();
@@ -238,27 +3320,2109 @@ mod tests {
     }
 
     #[test]
-    fn it_works() {
-        let mut set = FastSet::new(234).unwrap();
-        assert!(!set.contains(5));
-        assert!(!set.contains(300));
-        set.add(5).unwrap();
+    fn capacities() {
+        let set = FastSet::new(17).unwrap();
+        assert_eq!(set.sparse_capacity(), 17);
+        assert_eq!(set.backref_capacity(), 17);
+    }
+
+    #[test]
+    fn it_works() {
+        let mut set = FastSet::new(234).unwrap();
+        assert!(!set.contains(5));
+        assert!(!set.contains(300));
+        set.add(5).unwrap();
+        set.add(3).unwrap();
+        set.add(5).unwrap();
+        assert!(set.contains(3));
+        assert!(!set.contains(4));
+        assert!(set.contains(5));
+        assert_eq!(set.len(), 2);
+        set.remove(3).unwrap();
+        assert!(set.contains(5));
+        assert!(!set.contains(3));
+        assert_eq!(set.len(), 1);
+        for key in &set {
+            assert_eq!(*key, 5);
+        }
+        let other = set.clone();
+        set.clear();
+        assert!(!set.contains(5));
+        assert!(other.contains(5));
+    }
+
+    #[test]
+    fn from_sorted_slice() {
+        let set = FastSet::from_sorted_slice(10, &[1, 4, 9]).unwrap();
+        set.validate_bounds().unwrap();
+        assert_eq!(set.len(), 3);
+        assert!(set.contains(1) && set.contains(4) && set.contains(9));
+        assert!(!set.contains(0) && !set.contains(5));
+        let err = FastSet::from_sorted_slice(10, &[1, 10]).err().unwrap();
+        assert_eq!(err.key, 10);
+        assert!(FastSet::from_sorted_slice(2, &[0, 1, 1]).is_err());
+        let err = FastSet::from_sorted_slice(2, &[1, 1]).err().unwrap();
+        assert_eq!((err.cap, err.key), (2, 1));
+        let err = FastSet::from_sorted_slice(10, &[2, 7, 5, 8]).err().unwrap();
+        assert_eq!(err.key, 5);
+    }
+
+    #[test]
+    fn fits() {
+        let set = FastSet::new(10).unwrap();
+        assert_eq!(FastSet::max_key(&[3, 9, 2]), Some(9));
+        assert_eq!(FastSet::max_key(&[]), None);
+        assert!(set.fits(&[3, 9, 2]));
+        assert!(set.fits(&[]));
+        assert!(!set.fits(&[3, 10, 2]));
+    }
+
+    #[test]
+    fn toggle_all() {
+        let mut set = FastSet::new(10).unwrap();
+        for key in [1, 2, 3, 4] {
+            set.add(key).unwrap();
+        }
+        set.toggle_all([1, 5, 3, 6, 6]).unwrap();
+        let mut keys = set.keys().to_vec();
+        keys.sort();
+        assert_eq!(keys, [2, 4, 5]);
+        assert!(set.toggle_all([7, 10, 8]).is_err());
+        assert!(set.contains(7));
+        assert!(!set.contains(8));
+    }
+
+    #[test]
+    fn unique_across() {
+        let mut a = FastSet::new(10).unwrap();
+        let mut b = FastSet::new(10).unwrap();
+        let mut c = FastSet::new(20).unwrap();
+        a.toggle_all([0, 1, 2, 3]).unwrap();
+        b.toggle_all([2, 3, 4, 5]).unwrap();
+        c.toggle_all([3, 5, 6, 15]).unwrap();
+        let unique = FastSet::unique_across([&a, &b, &c], 10);
+        assert_eq!(unique.cap(), 10);
+        let mut keys = unique.keys().to_vec();
+        keys.sort();
+        assert_eq!(keys, [0, 1, 4, 6]);
+    }
+
+    #[test]
+    fn debug() {
+        let mut set = FastSet::new(100).unwrap();
+        set.toggle_all([7, 3, 5]).unwrap();
+        assert_eq!(format!("{:?}", set), "{3, 5, 7}");
+        set.toggle_all((20..40).rev()).unwrap();
+        let expected = format!(
+            "{{3, 5, 7, {}, … (23 total)}}",
+            (20..33)
+                .map(|k| k.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        assert_eq!(format!("{:?}", set), expected);
+        assert_eq!(set.to_sorted_vec().len(), 23);
+    }
+
+    #[test]
+    fn dice() {
+        let mut a = FastSet::new(10).unwrap();
+        let mut b = FastSet::new(20).unwrap();
+        assert_eq!(a.dice(&b), 1.0);
+        a.toggle_all([1, 2, 3, 4]).unwrap();
+        assert_eq!(a.dice(&b), 0.0);
+        b.toggle_all([3, 4, 5, 15]).unwrap();
+        assert_eq!(a.intersection_len(&b), 2);
+        assert_eq!(a.dice(&b), 0.5);
+        assert_eq!(a.dice(&a), 1.0);
+    }
+
+    #[test]
+    fn load_keys() {
+        let bytes: Vec<u8> = (0..1000u64).flat_map(|k| (k * 3).to_le_bytes()).collect();
+        let mut set = FastSet::new(3000).unwrap();
+        set.load_keys(&mut &bytes[..], 1000).unwrap();
+        assert_eq!(set.len(), 1000);
+        assert!(set.contains(2997));
+        assert!(!set.contains(2998));
+
+        let mut small = FastSet::new(100).unwrap();
+        let err = small.load_keys(&mut &bytes[..], 1000).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(small.len(), 34);
+        let err = small.load_keys(&mut &bytes[..16], 3).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn into() {
+        let mut a = FastSet::new(10).unwrap();
+        let mut b = FastSet::new(20).unwrap();
+        a.toggle_all([1, 2, 3]).unwrap();
+        b.toggle_all([3, 4, 15]).unwrap();
+        assert_eq!(a.union_cap(&b), 20);
+        assert_eq!(a.intersection_cap(&b), 10);
+
+        let mut dest = FastSet::new(a.union_cap(&b)).unwrap();
+        a.union_into(&b, &mut dest).unwrap();
+        assert_eq!(dest.to_sorted_vec(), [1, 2, 3, 4, 15]);
+        a.intersection_into(&b, &mut dest).unwrap();
+        assert_eq!(dest.to_sorted_vec(), [3]);
+
+        let mut small = FastSet::new(a.intersection_cap(&b)).unwrap();
+        assert!(a.union_into(&b, &mut small).is_err());
+        a.intersection_into(&b, &mut small).unwrap();
+        assert_eq!(small.to_sorted_vec(), [3]);
+    }
+
+    #[test]
+    fn contains_range() {
+        let mut set = FastSet::new(10).unwrap();
+        set.toggle_all([2, 3, 4, 5, 8]).unwrap();
+        assert!(set.contains_range(2..6));
+        assert!(set.contains_range(8..9));
+        assert!(!set.contains_range(2..7));
+        assert!(!set.contains_range(0..10));
+        assert!(!set.contains_range(8..11));
+        assert!(set.contains_range(7..7));
+        assert!(set.contains_range(20..20));
+    }
+
+    #[test]
+    fn runs() {
+        let mut set = FastSet::new(20).unwrap();
+        assert!(set.to_runs().is_empty());
+        set.toggle_all([9, 1, 2, 3, 7, 19, 8]).unwrap();
+        assert_eq!(set.to_runs(), [1..4, 7..10, 19..20]);
+        let split = set.split_into_runs();
+        assert_eq!(split.len(), 3);
+        assert_eq!(split[1].to_sorted_vec(), [7, 8, 9]);
+        assert!(split.iter().all(|run| run.cap() == 20));
+    }
+
+    #[test]
+    fn without_range() {
+        let mut set = FastSet::new(10).unwrap();
+        set.toggle_all([1, 3, 5, 7, 9]).unwrap();
+        let rest = set.without_range(4..8);
+        assert_eq!(rest.cap(), 10);
+        assert_eq!(rest.to_sorted_vec(), [1, 3, 9]);
+        assert_eq!(
+            set.without_range(8..usize::MAX).to_sorted_vec(),
+            [1, 3, 5, 7]
+        );
+        assert_eq!(set.len(), 5);
+    }
+
+    #[test]
+    #[cfg(feature = "rand")]
+    fn iter_shuffled() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut set = FastSet::new(100).unwrap();
+        set.toggle_all(0..50).unwrap();
+        let before = set.keys().to_vec();
+        let mut rng = StdRng::seed_from_u64(12345);
+        let mut shuffled: Vec<usize> = set.iter_shuffled(&mut rng).collect();
+        assert_eq!(set.keys(), &before[..]);
+        assert_ne!(shuffled, before);
+        shuffled.sort();
+        assert_eq!(shuffled, before);
+    }
+
+    #[test]
+    fn high_water() {
+        let mut set = FastSet::new(100).unwrap();
+        assert_eq!(set.high_water(), (0, 0));
+        set.toggle_all([5, 80, 3]).unwrap();
+        set.remove(80).unwrap();
+        set.clear();
+        set.add(7).unwrap();
+        assert_eq!(set.high_water(), (3, 80));
+        assert_eq!(set.clone().high_water(), (1, 7));
+        let mut other = FastSet::new(100).unwrap();
+        other.add(99).unwrap();
+        other.clone_from(&set);
+        assert_eq!(other.high_water(), (1, 7));
+        let sorted = FastSet::from_sorted_slice(10, &[2, 4]).unwrap();
+        assert_eq!(sorted.high_water(), (2, 4));
+    }
+
+    #[test]
+    fn recycle() {
+        let mut set = FastSet::new(10).unwrap();
+        for new_cap in [10, 50, 3, 0, 8] {
+            set.toggle_all(0..set.cap()).unwrap();
+            set.recycle(new_cap).unwrap();
+            assert_eq!(set.cap(), new_cap);
+            assert!(set.is_empty());
+            assert!((0..new_cap).all(|key| !set.contains(key)));
+        }
+        assert!(set.recycle(usize::MAX).is_err());
+        assert_eq!(set.cap(), 8);
+        let empty = FastSet::new(0).unwrap();
+        assert!(!empty.contains(0));
+    }
+
+    #[test]
+    fn to_bloom() {
+        let mut set = FastSet::new(1000).unwrap();
+        set.toggle_all((0..1000).step_by(7)).unwrap();
+        let bits = 2000;
+        let filter = set.to_bloom(bits, 3);
+        assert_eq!(filter.len(), 32);
+        let maybe_contains = |key: usize| {
+            let h1 = mix64(key as u64);
+            let h2 = mix64(h1) | 1;
+            (0..3u64).all(|i| {
+                let bit = (h1.wrapping_add(i.wrapping_mul(h2)) % bits as u64) as usize;
+                filter[bit / 64] & (1 << (bit % 64)) != 0
+            })
+        };
+        assert!(set.keys().iter().all(|&key| maybe_contains(key)));
+        let false_positives = (0..1000).filter(|&k| !set.contains(k) && maybe_contains(k));
+        assert!(false_positives.count() < 300);
+    }
+
+    #[test]
+    fn matches_reference() {
+        let mut set = FastSet::new(5).unwrap();
+        set.toggle_all([1, 3]).unwrap();
+        assert!(set.matches_reference(&[false, true, false, true, false]));
+        assert!(set.matches_reference(&[false, true, false, true]));
+        assert!(set.matches_reference(&[false, true, false, true, false, false, false]));
+        assert!(!set.matches_reference(&[false, true, false, true, false, false, true]));
+        assert!(!set.matches_reference(&[false, true]));
+        assert!(!set.matches_reference(&[true, true, false, true]));
+    }
+
+    #[test]
+    fn sorted_cached() {
+        let mut set = FastSet::new(10).unwrap();
+        set.toggle_all([5, 2, 8]).unwrap();
+        assert_eq!(set.sorted_cached(), [2, 5, 8]);
+        assert_eq!(set.sorted_cached(), [2, 5, 8]);
+        set.add(1).unwrap();
+        assert_eq!(set.sorted_cached(), [1, 2, 5, 8]);
+        let buffer = set.sorted_cached().as_ptr();
+        set.remove(5).unwrap();
+        assert_eq!(set.sorted_cached(), [1, 2, 8]);
+        assert_eq!(set.sorted_cached().as_ptr(), buffer);
+        set.toggle(3).unwrap();
+        assert_eq!(set.clone().sorted_cached(), [1, 2, 3, 8]);
+        set.clear();
+        assert!(set.sorted_cached().is_empty());
+        set.recycle(3).unwrap();
+        set.add(0).unwrap();
+        assert_eq!(set.sorted_cached(), [0]);
+    }
+
+    #[test]
+    fn iter_descending() {
+        let mut sparse = FastSet::new(1000).unwrap();
+        sparse.toggle_all([5, 900, 17]).unwrap();
+        assert_eq!(sparse.iter_descending().collect::<Vec<_>>(), [900, 17, 5]);
+        let mut dense = FastSet::new(10).unwrap();
+        dense.toggle_all([5, 9, 1, 2, 7]).unwrap();
+        assert_eq!(dense.iter_descending().collect::<Vec<_>>(), [9, 7, 5, 2, 1]);
+        assert_eq!(FastSet::new(0).unwrap().iter_descending().count(), 0);
+    }
+
+    #[test]
+    fn to_index_map() {
+        let mut set = FastSet::new(100).unwrap();
+        set.toggle_all([40, 3, 99, 17]).unwrap();
+        let map = set.to_index_map();
+        assert_eq!(map.len(), 4);
+        assert_eq!(map[&3], 0);
+        assert_eq!(map[&17], 1);
+        assert_eq!(map[&40], 2);
+        assert_eq!(map[&99], 3);
+    }
+
+    #[test]
+    fn overlap_coefficient() {
+        let mut a = FastSet::new(10).unwrap();
+        let mut b = FastSet::new(10).unwrap();
+        assert_eq!(a.overlap_coefficient(&b), 1.0);
+        a.toggle_all([1, 2]).unwrap();
+        b.toggle_all([1, 2, 3, 4, 5]).unwrap();
+        assert_eq!(a.overlap_coefficient(&b), 1.0);
+        a.toggle_all([2, 6]).unwrap();
+        assert_eq!(b.overlap_coefficient(&a), 0.5);
+    }
+
+    #[test]
+    fn add_at() {
+        let mut set = FastSet::new(10).unwrap();
+        assert_eq!(set.add_at(7).unwrap(), 0);
+        assert_eq!(set.add_at(3).unwrap(), 1);
+        assert_eq!(set.add_at(5).unwrap(), 2);
+        assert_eq!(set.add_at(3).unwrap(), 1);
+        set.remove(7).unwrap();
+        assert_eq!(set.add_at(5).unwrap(), 0);
+        assert_eq!(set.keys()[0], 5);
+        assert!(set.add_at(10).is_err());
+    }
+
+    #[test]
+    fn fast_map() {
+        let mut map = FastMap::new(10).unwrap();
+        assert_eq!(map.insert(3, "three").unwrap(), None);
+        assert_eq!(map.insert(7, "seven").unwrap(), None);
+        assert_eq!(map.insert(5, "five").unwrap(), None);
+        assert_eq!(map.insert(7, "SEVEN").unwrap(), Some("seven"));
+        assert!(map.insert(10, "ten").is_err());
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get(7), Some(&"SEVEN"));
+        assert_eq!(map.get(4), None);
+        assert_eq!(map.get(40), None);
+
+        assert_eq!(map.remove(3), Some("three"));
+        assert_eq!(map.remove(3), None);
+        assert_eq!(map.get(5), Some(&"five"));
+        assert_eq!(map.get(7), Some(&"SEVEN"));
+        *map.get_mut(5).unwrap() = "FIVE";
+        let mut entries: Vec<_> = map.iter().collect();
+        entries.sort();
+        assert_eq!(entries, [(5, &"FIVE"), (7, &"SEVEN")]);
+        assert_eq!(format!("{:?}", map.clone()), format!("{:?}", map));
+
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.get(5), None);
+        map.insert(5, "again").unwrap();
+        assert_eq!(map.get(5), Some(&"again"));
+    }
+
+    #[test]
+    fn coverage() {
+        let mut got = FastSet::new(10).unwrap();
+        let mut expected = FastSet::new(20).unwrap();
+        assert_eq!(got.coverage(&expected), 1.0);
+        expected.toggle_all([1, 2, 3, 15]).unwrap();
+        assert_eq!(got.coverage(&expected), 0.0);
+        got.toggle_all([1, 3, 4, 5, 6, 7]).unwrap();
+        assert_eq!(got.coverage(&expected), 0.5);
+    }
+
+    #[test]
+    fn runs_iter() {
+        let mut set = FastSet::new(10).unwrap();
+        assert_eq!(set.runs_iter().collect::<Vec<_>>(), [(false, 0..10)]);
+        set.toggle_all([0, 1, 4, 5, 6]).unwrap();
+        let expected = [(true, 0..2), (false, 2..4), (true, 4..7), (false, 7..10)];
+        assert_eq!(set.runs_iter().collect::<Vec<_>>(), expected);
+        set.toggle_all([0, 1, 9]).unwrap();
+        let expected = [(false, 0..4), (true, 4..7), (false, 7..9), (true, 9..10)];
+        assert_eq!(set.runs_iter().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn dedup_first() {
+        let mut seen = FastSet::new(10).unwrap();
+        seen.add(4).unwrap();
+        let fresh = seen.dedup_first([3, 4, 3, 11, 7, 3, 7, 0]);
+        assert_eq!(fresh, [3, 7, 0]);
+        assert_eq!(seen.to_sorted_vec(), [0, 3, 4, 7]);
+    }
+
+    #[test]
+    fn symmetric_difference_into() {
+        let mut a = FastSet::new(10).unwrap();
+        let mut b = FastSet::new(20).unwrap();
+        a.toggle_all([1, 2, 3]).unwrap();
+        b.toggle_all([3, 4, 15]).unwrap();
+        let mut dest = FastSet::new(a.union_cap(&b)).unwrap();
+        a.symmetric_difference_into(&b, &mut dest).unwrap();
+        assert_eq!(dest.to_sorted_vec(), [1, 2, 4, 15]);
+        b.toggle_all([1, 2, 15]).unwrap();
+        a.symmetric_difference_into(&b, &mut dest).unwrap();
+        assert_eq!(dest.to_sorted_vec(), [4]);
+        let mut small = FastSet::new(10).unwrap();
+        b.add(12).unwrap();
+        assert!(a.symmetric_difference_into(&b, &mut small).is_err());
+    }
+
+    #[test]
+    fn is_arithmetic() {
+        let mut set = FastSet::new(100).unwrap();
+        assert_eq!(set.is_arithmetic(), Some(0));
+        set.add(40).unwrap();
+        assert_eq!(set.is_arithmetic(), Some(0));
+        set.toggle_all([10, 25, 55]).unwrap();
+        assert_eq!(set.is_arithmetic(), Some(15));
+        set.add(60).unwrap();
+        assert_eq!(set.is_arithmetic(), None);
+    }
+
+    #[test]
+    fn from_bool_slice() {
+        let mask = [false, true, true, false, true];
+        let set = FastSet::from_bool_slice(&mask).unwrap();
+        set.validate_bounds().unwrap();
+        assert_eq!(set.cap(), 5);
+        assert_eq!(set.to_sorted_vec(), [1, 2, 4]);
+        assert!(set.matches_reference(&mask));
+        let none = FastSet::from_bool_slice(&[false; 3]).unwrap();
+        assert_eq!(none.cap(), 3);
+        assert!(none.is_empty());
+    }
+
+    #[test]
+    fn to_bool_vec() {
+        let mut set = FastSet::new(6).unwrap();
+        set.toggle_all([0, 3, 5]).unwrap();
+        let mask = set.to_bool_vec();
+        assert_eq!(mask.len(), 6);
+        assert_eq!(mask, [true, false, false, true, false, true]);
+        let round_trip = FastSet::from_bool_slice(&mask).unwrap();
+        assert_eq!(round_trip.cap(), 6);
+        assert_eq!(round_trip.to_sorted_vec(), [0, 3, 5]);
+        assert_eq!(FastSet::new(4).unwrap().to_bool_vec(), [false; 4]);
+    }
+
+    #[test]
+    fn new_with_limit() {
+        let word = std::mem::size_of::<usize>();
+        let set = FastSet::new_with_limit(100, 200 * word).unwrap();
+        assert_eq!(set.cap(), 100);
+        assert!(matches!(
+            FastSet::new_with_limit(100, 200 * word - 1),
+            Err(FastSetError::LimitExceeded { cap: 100, .. })
+        ));
+        assert!(matches!(
+            FastSet::new_with_limit(usize::MAX, usize::MAX),
+            Err(FastSetError::LimitExceeded { .. })
+        ));
+    }
+
+    #[test]
+    fn byte_offsets() {
+        let mut set = FastSet::new(10).unwrap();
+        set.toggle_all([0, 3, 7]).unwrap();
+        assert_eq!(set.byte_offsets(4).collect::<Vec<_>>(), [0, 12, 28]);
+        let buf: Vec<u8> = (0..40).collect();
+        let gathered: Vec<u8> = set.byte_offsets(4).map(|offset| buf[offset]).collect();
+        assert_eq!(gathered, [0, 12, 28]);
+    }
+
+    #[test]
+    fn mean() {
+        let mut set = FastSet::new(100).unwrap();
+        assert_eq!(set.mean(), None);
+        set.toggle_all([10, 20, 60]).unwrap();
+        assert_eq!(set.mean(), Some(30.0));
+        set.add(1).unwrap();
+        assert_eq!(set.mean(), Some(22.75));
+    }
+
+    #[test]
+    fn new_for_max() {
+        let mut set = FastSet::new_for_max(9).unwrap();
+        assert_eq!(set.cap(), 10);
+        set.add(9).unwrap();
+        assert_eq!(FastSet::new_for_max(0).unwrap().cap(), 1);
+        assert!(FastSet::new_for_max(usize::MAX).is_err());
+    }
+
+    #[test]
+    fn is_subset() {
+        let mut a = FastSet::new(1000).unwrap();
+        let mut b = FastSet::new(500).unwrap();
+        assert!(a.is_subset(&b) && a.is_subset_dense(&b));
+        a.toggle_all([3, 70, 400]).unwrap();
+        b.toggle_all([3, 70, 400, 401]).unwrap();
+        assert!(a.is_subset(&b) && a.is_subset_dense(&b));
+        assert!(!b.is_subset(&a) && !b.is_subset_dense(&a));
+        a.add(600).unwrap();
+        assert!(!a.is_subset(&b) && !a.is_subset_dense(&b));
+
+        let mut dense = FastSet::new(1000).unwrap();
+        dense.toggle_all((0..1000).step_by(3)).unwrap();
+        let mut superset = dense.clone();
+        superset.toggle_all((1..1000).step_by(3)).unwrap();
+        assert!(dense.is_subset(&superset));
+        superset.remove(999).unwrap();
+        assert!(!dense.is_subset(&superset));
+    }
+
+    #[test]
+    fn copy_keys_to() {
+        let mut set = FastSet::new(10).unwrap();
+        set.toggle_all([4, 1, 8]).unwrap();
+        let mut big = [0; 5];
+        assert_eq!(set.copy_keys_to(&mut big), 3);
+        assert_eq!(big, [4, 1, 8, 0, 0]);
+        let mut small = [0; 2];
+        assert_eq!(set.copy_keys_to(&mut small), 2);
+        assert_eq!(small, [4, 1]);
+    }
+
+    #[test]
+    fn as_contiguous() {
+        let mut set = FastSet::new(10).unwrap();
+        assert_eq!(set.as_contiguous(), None);
+        assert_eq!((set.min(), set.max()), (None, None));
+        set.toggle_all([5, 3, 4, 6]).unwrap();
+        assert_eq!((set.min(), set.max()), (Some(3), Some(6)));
+        assert_eq!(set.as_contiguous(), Some(3..7));
+        set.add(8).unwrap();
+        assert_eq!(set.as_contiguous(), None);
+    }
+
+    #[test]
+    fn gap_count() {
+        let mut set = FastSet::new(10).unwrap();
+        assert_eq!(set.gap_count(), 1);
+        assert_eq!(FastSet::new(0).unwrap().gap_count(), 0);
+        set.toggle_all([0, 4, 5, 9]).unwrap();
+        assert_eq!(set.gap_count(), 2);
+        set.toggle_all([0, 9]).unwrap();
+        assert_eq!(set.gap_count(), 2);
+        set.toggle_all(0..10).unwrap();
+        set.toggle_all([4, 5]).unwrap();
+        assert_eq!(set.len(), 10);
+        assert_eq!(set.gap_count(), 0);
+    }
+
+    #[test]
+    fn adjacent_gaps() {
+        let mut set = FastSet::new(20).unwrap();
+        assert_eq!(set.adjacent_gaps().count(), 0);
+        set.add(7).unwrap();
+        assert_eq!(set.adjacent_gaps().count(), 0);
+        set.toggle_all([15, 1, 8]).unwrap();
+        assert_eq!(set.adjacent_gaps().collect::<Vec<_>>(), [6, 1, 7]);
+    }
+
+    #[test]
+    #[cfg(feature = "rand")]
+    fn random_subset() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(987654321);
+        for k in [0, 10, 50, 90, 100, 150] {
+            let set = FastSet::random_subset(100, k, &mut rng);
+            set.validate_bounds().unwrap();
+            assert_eq!(set.cap(), 100);
+            assert_eq!(set.len(), k.min(100));
+        }
+    }
+
+    #[test]
+    fn edit_script() {
+        let mut a = FastSet::new(10).unwrap();
+        let mut b = FastSet::new(20).unwrap();
+        a.toggle_all([1, 2, 3]).unwrap();
+        b.toggle_all([3, 4, 15]).unwrap();
+        let (mut to_add, mut to_remove) = a.edit_script(&b);
+        to_add.sort();
+        to_remove.sort();
+        assert_eq!((to_add, to_remove), (vec![4, 15], vec![1, 2]));
+
+        b.toggle_all([3, 4, 15, 7]).unwrap();
+        let (to_add, mut to_remove) = a.edit_script(&b);
+        to_remove.sort();
+        assert_eq!((to_add, to_remove), (vec![7], vec![1, 2, 3]));
+
+        let (to_add, to_remove) = a.edit_script(&a.clone());
+        assert!(to_add.is_empty() && to_remove.is_empty());
+    }
+
+    #[test]
+    fn apply_edits() {
+        let mut a = FastSet::new(20).unwrap();
+        let mut b = FastSet::new(20).unwrap();
+        a.toggle_all([1, 2, 3, 9]).unwrap();
+        b.toggle_all([3, 4, 15, 9]).unwrap();
+        let (to_add, to_remove) = a.edit_script(&b);
+        a.apply_edits(&to_add, &to_remove).unwrap();
+        assert_eq!(a.to_sorted_vec(), b.to_sorted_vec());
+        assert_eq!(a.apply_edits(&[30], &[]).unwrap_err().key, 30);
+        assert_eq!(a.apply_edits(&[], &[20]).unwrap_err().key, 20);
+    }
+
+    #[test]
+    fn reporting() {
+        let mut set = FastSet::new(10).unwrap();
+        assert!(set.add_reporting(3).unwrap());
+        assert!(!set.add_reporting(3).unwrap());
+        assert!(set.remove_reporting(3).unwrap());
+        assert!(!set.remove_reporting(3).unwrap());
+        assert!(set.add_reporting(10).is_err());
+        assert!(set.remove_reporting(10).is_err());
+        assert_eq!(set.add_all_reporting([1, 2, 2, 5, 1]).unwrap(), 3);
+        assert_eq!(set.remove_all_reporting([1, 4, 5, 5]).unwrap(), 2);
+        assert_eq!(set.to_sorted_vec(), [2]);
+        assert!(set.add_all_reporting([6, 11]).is_err());
+        assert!(set.contains(6));
+    }
+
+    #[test]
+    fn into_sorted_frozen() {
+        fn assert_send_sync<T: Send + Sync + Clone>() {}
+        assert_send_sync::<SortedSet>();
+
+        let mut set = FastSet::new(50).unwrap();
+        set.toggle_all([30, 2, 17, 49, 0]).unwrap();
+        let copy = set.clone();
+        let frozen = set.into_sorted_frozen();
+        assert_eq!(frozen.len(), 5);
+        assert_eq!(frozen.keys(), [0, 2, 17, 30, 49]);
+        for key in 0..100 {
+            assert_eq!(frozen.contains(key), copy.contains(key));
+        }
+        assert_eq!(
+            frozen.iter().copied().collect::<Vec<_>>(),
+            copy.to_sorted_vec()
+        );
+    }
+
+    #[test]
+    fn grow_complement_semantics() {
+        for new_present in [false, true] {
+            let mut set = FastSet::new(0).unwrap();
+            set.grow_complement_semantics(5, false).unwrap();
+            set.toggle_all([1, 3, 4]).unwrap();
+            set.remove(4).unwrap();
+            set.grow_complement_semantics(8, new_present).unwrap();
+            assert_eq!(set.cap(), 8);
+            let expected: &[usize] = if new_present {
+                &[1, 3, 5, 6, 7]
+            } else {
+                &[1, 3]
+            };
+            assert_eq!(set.to_sorted_vec(), expected);
+            assert!(!set.contains(4));
+            set.add(4).unwrap();
+            set.grow_complement_semantics(3, true).unwrap();
+            assert_eq!(set.cap(), 8);
+            assert!(set.grow_complement_semantics(usize::MAX, true).is_err());
+            assert_eq!(set.len(), expected.len() + 1);
+        }
+    }
+
+    #[test]
+    fn range_digests() {
+        let mut a = FastSet::new(100).unwrap();
+        let mut b = FastSet::new(100).unwrap();
+        a.toggle_all([5, 50, 51, 99]).unwrap();
+        b.toggle_all([99, 51, 5, 50]).unwrap();
+        let digests = a.range_digests(30);
+        assert_eq!(digests.len(), 4);
+        assert_eq!(digests, b.range_digests(30));
+        assert_eq!(digests[2], 0);
+        b.remove(51).unwrap();
+        let other = b.range_digests(30);
+        assert_eq!((digests[0], digests[3]), (other[0], other[3]));
+        assert_ne!(digests[1], other[1]);
+    }
+
+    #[test]
+    fn block_masks() {
+        let mut set = FastSet::new(1000).unwrap();
+        set.toggle_all([130, 0, 63, 129, 999]).unwrap();
+        let blocks: Vec<_> = set.block_masks().collect();
+        assert_eq!(blocks, [(0, 1 | 1 << 63), (128, 0b110), (960, 1 << 39)]);
+    }
+
+    #[test]
+    fn union_len_many() {
+        let mut a = FastSet::new(10).unwrap();
+        let mut b = FastSet::new(10).unwrap();
+        let mut c = FastSet::new(30).unwrap();
+        a.toggle_all([0, 1, 2]).unwrap();
+        b.toggle_all([2, 3]).unwrap();
+        c.toggle_all([3, 4, 25]).unwrap();
+        let mut union = FastSet::new(30).unwrap();
+        a.union_into(&b, &mut union).unwrap();
+        let ab = union.clone();
+        ab.union_into(&c, &mut union).unwrap();
+        assert_eq!(FastSet::union_len_many([&a, &b, &c], 30), union.len());
+        assert_eq!(FastSet::union_len_many([&a, &b, &c], 10), 5);
+        let mut scratch = FastSet::new(30).unwrap();
+        scratch.add(29).unwrap();
+        assert_eq!(FastSet::union_len_many_with([&a, &b, &c], &mut scratch), 6);
+        assert_eq!(FastSet::union_len_many_with([&a, &b], &mut scratch), 4);
+    }
+
+    #[test]
+    fn try_as_range() {
+        let mut ids = FastSet::new(10).unwrap();
+        assert_eq!(ids.try_as_range(), Some(0..0));
+        for id in 0..5 {
+            ids.add(id).unwrap();
+            assert_eq!(ids.try_as_range(), Some(0..id + 1));
+        }
+        ids.remove(2).unwrap();
+        assert_eq!(ids.try_as_range(), None);
+        ids.remove(0).unwrap();
+        ids.remove(1).unwrap();
+        assert_eq!(ids.as_contiguous(), Some(3..5));
+        assert_eq!(ids.try_as_range(), None);
+    }
+
+    #[test]
+    fn present_indices() {
+        let mut set = FastSet::new(10).unwrap();
+        set.toggle_all([2, 5, 7]).unwrap();
+        let queries = [5, 1, 20, 7, 7, 3, 2];
+        assert_eq!(set.present_indices(&queries), [0, 3, 4, 6]);
+        assert_eq!(set.present_values(&queries), [5, 7, 7, 2]);
+        assert!(set.present_indices(&[]).is_empty());
+    }
+
+    #[test]
+    fn largest_gap() {
+        let mut set = FastSet::new(20).unwrap();
+        assert_eq!(set.largest_gap(), Some((0, 20)));
+        set.toggle_all([8, 12]).unwrap();
+        assert_eq!(set.largest_gap(), Some((0, 8)));
+        set.toggle_all(0..4).unwrap();
+        assert_eq!(set.largest_gap(), Some((13, 7)));
+        set.toggle_all(13..18).unwrap();
+        assert_eq!(set.largest_gap(), Some((4, 4)));
+        set.toggle_all([5, 6, 7]).unwrap();
+        assert_eq!(set.largest_gap(), Some((9, 3)));
+        set.toggle_all([4, 9, 10, 11, 18, 19]).unwrap();
+        assert_eq!(set.largest_gap(), None);
+    }
+
+    #[test]
+    fn iter_morton() {
+        let mut grid = FastSet::new(16).unwrap();
+        grid.toggle_all(0..16).unwrap();
+        let order: Vec<_> = grid.iter_morton().collect();
+        assert_eq!(
+            order,
+            [0, 1, 4, 5, 2, 3, 6, 7, 8, 9, 12, 13, 10, 11, 14, 15]
+        );
+
+        // An 8x4 grid: x takes 3 bits, y the other 2
+        let mut set = FastSet::new(32).unwrap();
+        set.toggle_all([31, 8, 7, 1]).unwrap();
+        assert_eq!(set.iter_morton().collect::<Vec<_>>(), [1, 8, 7, 31]);
+    }
+
+    #[test]
+    fn intersection_report() {
+        let mut a = FastSet::new(10).unwrap();
+        let mut b = FastSet::new(10).unwrap();
+        assert_eq!(a.intersection_report(&b), (0, true));
+        a.toggle_all([1, 2, 3]).unwrap();
+        b.toggle_all([4, 5]).unwrap();
+        assert_eq!(a.intersection_report(&b), (0, true));
+        b.toggle_all([2, 3]).unwrap();
+        assert_eq!(a.intersection_report(&b), (2, false));
+    }
+
+    #[test]
+    fn from_sorted_trusted() {
+        let keys = [0, 3, 4, 9];
+        let set = unsafe { FastSet::from_sorted_trusted(10, &keys) };
+        set.validate_bounds().unwrap();
+        assert_eq!(set.to_sorted_vec(), keys);
+        assert!(!set.contains(5));
+        assert_eq!(set.high_water(), (4, 9));
+        let empty = unsafe { FastSet::from_sorted_trusted(0, &[]) };
+        empty.validate_bounds().unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn checksum() {
+        let mut a = FastSet::new(100).unwrap();
+        assert_eq!(a.checksum(), 0);
+        a.toggle_all([5, 70, 33]).unwrap();
+        assert!(a.verify_checksum(a.checksum()));
+        let mut b = FastSet::new(50).unwrap();
+        b.toggle_all([33, 5, 70 % 50]).unwrap();
+        assert!(!b.verify_checksum(a.checksum()));
+        b.recycle(100).unwrap();
+        b.toggle_all([33, 70, 5]).unwrap();
+        assert!(b.verify_checksum(a.checksum()));
+        let expected = a.checksum() ^ mix64(70);
+        a.remove(70).unwrap();
+        assert!(a.verify_checksum(expected));
+    }
+
+    #[test]
+    fn filter() {
+        let mut set = FastSet::new(10).unwrap();
+        set.toggle_all(0..10).unwrap();
+        let even = set.filter(|key| key % 2 == 0);
+        assert_eq!(even.cap(), 10);
+        assert_eq!(even.to_sorted_vec(), [0, 2, 4, 6, 8]);
+        assert_eq!(set.len(), 10);
+    }
+
+    #[test]
+    fn partition() {
+        let mut set = FastSet::new(20).unwrap();
+        set.toggle_all([1, 4, 6, 9, 13, 16]).unwrap();
+        let (small, big) = set.partition(|key| key < 8);
+        assert_eq!((small.cap(), big.cap()), (20, 20));
+        assert_eq!(small.to_sorted_vec(), [1, 4, 6]);
+        assert_eq!(big.to_sorted_vec(), [9, 13, 16]);
+        assert_eq!(small.intersection_len(&big), 0);
+        let mut union = set.empty_like();
+        small.union_into(&big, &mut union).unwrap();
+        assert_eq!(union.to_sorted_vec(), set.to_sorted_vec());
+    }
+
+    #[test]
+    fn compare_repr_bytes() {
+        let word = std::mem::size_of::<usize>();
+        let mut set = FastSet::new(1000).unwrap();
+        assert_eq!(set.compare_repr_bytes(), (2000 * word, 125, 0));
+        set.toggle_all(0..100).unwrap();
+        assert_eq!(set.compare_repr_bytes(), (2000 * word, 125, 100 * word));
+    }
+
+    #[test]
+    fn delta_from() {
+        let mut base = FastSet::new(20).unwrap();
+        let mut target = FastSet::new(20).unwrap();
+        base.toggle_all([9, 1, 2, 3, 15]).unwrap();
+        target.toggle_all([3, 18, 4, 9, 0]).unwrap();
+        let (added, removed) = target.delta_from(&base);
+        assert_eq!(added, [0, 4, 18]);
+        assert_eq!(removed, [1, 2, 15]);
+        let mut rebuilt = base.clone();
+        rebuilt.apply_delta(&added, &removed).unwrap();
+        assert_eq!(rebuilt.to_sorted_vec(), target.to_sorted_vec());
+    }
+
+    #[test]
+    fn containment_count() {
+        let mut a = FastSet::new(10).unwrap();
+        let mut b = FastSet::new(5).unwrap();
+        let mut c = FastSet::new(20).unwrap();
+        a.toggle_all([1, 7]).unwrap();
+        b.toggle_all([1, 2]).unwrap();
+        c.toggle_all([1, 7, 15]).unwrap();
+        let sets = [&a, &b, &c];
+        assert_eq!(FastSet::containment_count(sets, 1), 3);
+        assert_eq!(FastSet::containment_count(sets, 7), 2);
+        assert_eq!(FastSet::containment_count(sets, 15), 1);
+        assert_eq!(FastSet::containment_count(sets, 3), 0);
+        assert_eq!(FastSet::containment_count(sets, 100), 0);
+    }
+
+    #[test]
+    fn retain() {
+        let mut a = FastSet::new(20).unwrap();
+        a.toggle_all([4, 9, 2, 13, 6, 1, 18]).unwrap();
+        let mut b = a.clone();
+        let mut seen = Vec::new();
+        a.retain(|key| key % 2 == 0);
+        b.retain_deferred(|key| {
+            seen.push(key);
+            key % 2 == 0
+        });
+        assert_eq!(seen, [4, 9, 2, 13, 6, 1, 18]);
+        assert_eq!(a.to_sorted_vec(), [2, 4, 6, 18]);
+        assert_eq!(b.to_sorted_vec(), [2, 4, 6, 18]);
+        a.retain(|_| false);
+        assert!(a.is_empty());
+    }
+
+    #[test]
+    fn is_run() {
+        let mut set = FastSet::new(10).unwrap();
+        assert!(set.is_run(0..0));
+        assert!(set.is_run(7..7));
+        set.toggle_all([3, 4, 5]).unwrap();
+        assert!(set.is_run(3..6));
+        assert!(!set.is_run(3..5));
+        assert!(!set.is_run(4..7));
+        assert!(!set.is_run(2..6));
+        set.toggle_all([4, 6]).unwrap();
+        assert!(!set.is_run(3..6));
+        assert!(!set.is_run(8..11));
+    }
+
+    #[test]
+    fn min_cap() {
+        let mut set = FastSet::new(100).unwrap();
+        assert_eq!(set.min_cap(), 0);
+        set.toggle_all([0, 41, 7]).unwrap();
+        assert_eq!(set.min_cap(), 42);
+    }
+
+    #[test]
+    fn intersect_with_mask() {
+        let mut set = FastSet::new(100).unwrap();
+        set.toggle_all(0..100).unwrap();
+        set.intersect_with_mask(64, 0b1011);
+        assert_eq!(set.len(), 64 + 3);
+        assert!(set.contains(63) && set.contains(64) && set.contains(65));
+        assert!(!set.contains(66) && set.contains(67) && !set.contains(99));
+        set.intersect_with_mask(usize::MAX - 3, 0);
+        assert_eq!(set.len(), 67);
+    }
+
+    #[test]
+    fn iter_by_key() {
+        let cost = [5, 3, 9, 3, 1, 7];
+        let mut set = FastSet::new(6).unwrap();
+        set.toggle_all([2, 3, 0, 1, 5]).unwrap();
+        let order: Vec<_> = set.iter_by_key(|key| cost[key]).collect();
+        assert_eq!(order, [1, 3, 0, 5, 2]);
+    }
+
+    #[test]
+    fn union_with_reporting() {
+        let mut set = FastSet::new(4).unwrap();
+        set.toggle_all([0, 2, 3]).unwrap();
+        let mut other = FastSet::new(7).unwrap();
+        other.toggle_all([3, 1, 6, 0, 5]).unwrap();
+        assert_eq!(set.union_with_reporting(&other).unwrap(), [1, 6, 5]);
+        assert_eq!(set.cap(), 7);
+        assert_eq!(set.to_sorted_vec(), [0, 1, 2, 3, 5, 6]);
+        assert!(set.union_with_reporting(&other).unwrap().is_empty());
+
+        let small = FastSet::from_sorted_slice(2, &[1]).unwrap();
+        assert!(set.union_with_reporting(&small).unwrap().is_empty());
+        assert_eq!(set.cap(), 7);
+    }
+
+    #[test]
+    fn save_load() {
+        let mut set = FastSet::new(8).unwrap();
+        set.toggle_all([4, 1, 6]).unwrap();
+        let snapshot = set.save();
+        set.remove(1).unwrap();
+        set.toggle_all([0, 7]).unwrap();
+        set.load(&snapshot).unwrap();
+        assert_eq!(set.keys(), [4, 1, 6]);
+
+        let err = set.load(&[2, 8]).unwrap_err();
+        assert_eq!((err.cap, err.key), (8, 8));
+        assert_eq!(set.keys(), [4, 1, 6]);
+    }
+
+    #[test]
+    fn iter_in() {
+        let mut set = FastSet::new(10).unwrap();
+        set.toggle_all([8, 2, 5, 1]).unwrap();
+        let mut other = FastSet::new(6).unwrap();
+        other.toggle_all([1, 2, 3]).unwrap();
+        assert_eq!(set.iter_in(&other).collect::<Vec<_>>(), [2, 1]);
+        assert_eq!(set.iter_in(&other).next(), Some(2));
+        assert_eq!(other.iter_in(&set).collect::<Vec<_>>(), [1, 2]);
+    }
+
+    #[test]
+    fn window_coverage() {
+        let mut set = FastSet::new(10).unwrap();
+        set.toggle_all([0, 1, 2, 5, 9]).unwrap();
+        assert_eq!(set.window_coverage(4), [0.75, 0.25, 0.5]);
+        assert_eq!(set.window_coverage(10), [0.5]);
+        assert_eq!(set.window_coverage(20), [0.5]);
+        assert!(FastSet::new(0).unwrap().window_coverage(3).is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn window_coverage_zero() {
+        FastSet::new(4).unwrap().window_coverage(0);
+    }
+
+    #[test]
+    fn add_grow_reporting() {
+        let mut set = FastSet::new(2).unwrap();
+        let grew: Vec<_> = (0..9)
+            .map(|key| set.add_grow_reporting(key).unwrap())
+            .collect();
+        assert_eq!(
+            grew,
+            [false, false, true, false, true, false, false, false, true]
+        );
+        assert_eq!(set.cap(), 16);
+        assert_eq!(set.to_sorted_vec(), (0..9).collect::<Vec<_>>());
+
+        set.add_grow(40).unwrap();
+        assert_eq!(set.cap(), 41);
+        assert!(set.contains(40));
+        assert!(set.add_grow(usize::MAX).is_err());
+        assert_eq!(set.cap(), 41);
+    }
+
+    #[test]
+    fn total_symmetric_difference() {
+        let set = FastSet::from_sorted_slice(10, &[1, 2, 3]).unwrap();
+        let same = set.clone();
+        let disjoint = FastSet::from_sorted_slice(10, &[5, 6]).unwrap();
+        let overlapping = FastSet::from_sorted_slice(4, &[0, 2, 3]).unwrap();
+        assert_eq!(set.total_symmetric_difference([&same]), 0);
+        assert_eq!(set.total_symmetric_difference([&disjoint]), 5);
+        assert_eq!(set.total_symmetric_difference([&overlapping]), 2);
+        assert_eq!(
+            set.total_symmetric_difference([&same, &disjoint, &overlapping]),
+            7
+        );
+        assert_eq!(set.total_symmetric_difference([]), 0);
+    }
+
+    #[test]
+    fn from_stride() {
+        let set = FastSet::from_stride(10, 1, 3).unwrap();
+        set.validate_bounds().unwrap();
+        assert_eq!(set.cap(), 10);
+        assert_eq!(set.to_sorted_vec(), [1, 4, 7]);
+        assert_eq!(
+            FastSet::from_stride(9, 0, 2).unwrap().to_sorted_vec(),
+            [0, 2, 4, 6, 8]
+        );
+        assert_eq!(
+            FastSet::from_stride(5, 2, 1).unwrap().to_sorted_vec(),
+            [2, 3, 4]
+        );
+        assert_eq!(
+            FastSet::from_stride(5, 4, 100).unwrap().to_sorted_vec(),
+            [4]
+        );
+        assert!(FastSet::from_stride(5, 5, 1).unwrap().is_empty());
+        assert!(FastSet::from_stride(5, usize::MAX, 1).unwrap().is_empty());
+        let err = FastSet::from_stride(5, 2, 0).err().unwrap();
+        assert_eq!((err.cap, err.key), (5, 2));
+    }
+
+    #[test]
+    fn contains_inclusive_range() {
+        let set = FastSet::from_sorted_slice(8, &[2, 3, 4, 6, 7]).unwrap();
+        assert!(set.contains_inclusive_range(2, 4));
+        assert!(!set.contains_inclusive_range(2, 5));
+        assert!(set.contains_inclusive_range(6, 7));
+        assert!(set.contains_inclusive_range(6, 100));
+        assert!(set.contains_inclusive_range(6, usize::MAX));
+        assert!(!set.contains_inclusive_range(4, usize::MAX));
+        assert!(set.contains_inclusive_range(5, 4));
+        assert!(set.contains_inclusive_range(9, usize::MAX));
+        assert!(FastSet::new(0)
+            .unwrap()
+            .contains_inclusive_range(0, usize::MAX));
+    }
+
+    #[test]
+    fn split_n() {
+        let mut set = FastSet::new(10).unwrap();
+        set.toggle_all([5, 0, 9, 3, 7, 1, 2]).unwrap();
+        assert_eq!(set.split_n(3), [vec![5, 0, 9], vec![3, 7], vec![1, 2]]);
+        assert_eq!(set.split_n(1), [set.keys().to_vec()]);
+        let chunks = set.split_n(9);
+        assert_eq!(chunks.len(), 9);
+        assert!(chunks[..7].iter().all(|chunk| chunk.len() == 1));
+        assert!(chunks[7..].iter().all(Vec::is_empty));
+    }
+
+    #[test]
+    #[should_panic]
+    fn split_n_zero() {
+        FastSet::new(4).unwrap().split_n(0);
+    }
+
+    #[test]
+    fn complement_intersect() {
+        let mut set = FastSet::new(6).unwrap();
+        set.toggle_all([0, 2, 4, 5]).unwrap();
+        let mut other = FastSet::new(9).unwrap();
+        other.toggle_all([8, 2, 1, 5, 3]).unwrap();
+
+        let diff = set.difference(&other);
+        assert_eq!(diff.cap(), 6);
+        assert_eq!(diff.keys(), [0, 4]);
+
+        let new = set.complement_intersect(&other);
+        assert_eq!(new.cap(), 9);
+        assert_eq!(new.keys(), [8, 1, 3]);
+        assert_eq!(new.keys(), other.difference(&set).keys());
+    }
+
+    #[test]
+    fn with_alloc_hook() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let log = Rc::clone(&events);
+        let mut set =
+            FastSet::with_alloc_hook(4, move |event| log.borrow_mut().push(event)).unwrap();
+        let word = std::mem::size_of::<usize>();
         set.add(3).unwrap();
-        set.add(5).unwrap();
-        assert!(set.contains(3));
-        assert!(!set.contains(4));
-        assert!(set.contains(5));
-        assert_eq!(set.len(), 2);
-        set.remove(3).unwrap();
-        assert!(set.contains(5));
-        assert!(!set.contains(3));
-        assert_eq!(set.len(), 1);
-        for key in &set {
-            assert_eq!(*key, 5);
+        set.add_grow(9).unwrap();
+        set.recycle(2).unwrap();
+        set.recycle(6).unwrap();
+        let copy = set.clone();
+        drop(copy);
+        drop(set);
+
+        use AllocEvent::*;
+        assert_eq!(
+            *events.borrow(),
+            [
+                Alloc { bytes: 4 * word },
+                Alloc { bytes: 4 * word },
+                Realloc {
+                    old_bytes: 4 * word,
+                    new_bytes: 10 * word
+                },
+                Realloc {
+                    old_bytes: 4 * word,
+                    new_bytes: 10 * word
+                },
+                Realloc {
+                    old_bytes: 10 * word,
+                    new_bytes: 2 * word
+                },
+                Realloc {
+                    old_bytes: 10 * word,
+                    new_bytes: 2 * word
+                },
+                Dealloc { bytes: 2 * word },
+                Alloc { bytes: 6 * word },
+                Realloc {
+                    old_bytes: 2 * word,
+                    new_bytes: 6 * word
+                },
+                Dealloc { bytes: 6 * word },
+                Dealloc { bytes: 6 * word },
+            ]
+        );
+    }
+
+    #[test]
+    fn with_alloc_hook_zero_cap() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let log = Rc::clone(&events);
+        let mut set =
+            FastSet::with_alloc_hook(0, move |event| log.borrow_mut().push(event)).unwrap();
+        let word = std::mem::size_of::<usize>();
+        assert!(events.borrow().is_empty());
+        set.add_grow(2).unwrap();
+        set.recycle(0).unwrap();
+        set.recycle(5).unwrap();
+        set.recycle(0).unwrap();
+        drop(set);
+
+        use AllocEvent::*;
+        assert_eq!(
+            *events.borrow(),
+            [
+                Alloc { bytes: 3 * word },
+                Alloc { bytes: 3 * word },
+                Dealloc { bytes: 3 * word },
+                Dealloc { bytes: 3 * word },
+                Alloc { bytes: 5 * word },
+                Alloc { bytes: 5 * word },
+                Dealloc { bytes: 5 * word },
+                Dealloc { bytes: 5 * word },
+            ]
+        );
+    }
+
+    #[test]
+    fn with_alloc_hook_panicking() {
+        use std::cell::Cell;
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        use std::rc::Rc;
+
+        let armed = Rc::new(Cell::new(true));
+        let trigger = Rc::clone(&armed);
+        let mut set = FastSet::with_alloc_hook(4, move |event| {
+            if let AllocEvent::Dealloc { .. } = event {
+                if trigger.get() {
+                    panic!("hook panicked");
+                }
+            }
+        })
+        .unwrap();
+        set.add(3).unwrap();
+        assert!(catch_unwind(AssertUnwindSafe(|| set.recycle(1 << 16))).is_err());
+        set.validate_bounds().unwrap();
+        assert_eq!((set.cap(), set.len()), (1 << 16, 0));
+        set.add((1 << 16) - 1).unwrap();
+        armed.set(false);
+        set.recycle(8).unwrap();
+        armed.set(true);
+
+        // Dropped while unwinding, which frees the arrays once and skips the hook
+        assert!(catch_unwind(AssertUnwindSafe(move || {
+            set.recycle(1 << 16).unwrap();
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn canonical_key() {
+        let mut set = FastSet::new(1000).unwrap();
+        set.toggle_all([300, 1, 5]).unwrap();
+        let mut other = FastSet::new(400).unwrap();
+        other.toggle_all([5, 300, 1]).unwrap();
+        // deltas 1, 4, 295 = 0b10_0100111
+        assert_eq!(set.canonical_key(), "0104a702");
+        assert_eq!(other.canonical_key(), set.canonical_key());
+        other.remove(1).unwrap();
+        assert_ne!(other.canonical_key(), set.canonical_key());
+        assert_eq!(FastSet::new(3).unwrap().canonical_key(), "");
+    }
+
+    #[test]
+    fn merge_sorted_run() {
+        let mut set = FastSet::new(10).unwrap();
+        set.toggle_all([2, 7]).unwrap();
+        assert_eq!(set.merge_sorted_run(&[1, 2, 3, 3, 9]).unwrap(), 3);
+        assert_eq!(set.to_sorted_vec(), [1, 2, 3, 7, 9]);
+        assert_eq!(set.merge_sorted_run(&[]).unwrap(), 0);
+
+        let err = set.merge_sorted_run(&[0, 4, 10, 12]).unwrap_err();
+        assert_eq!((err.cap, err.key), (10, 10));
+        assert_eq!(set.to_sorted_vec(), [1, 2, 3, 7, 9]);
+
+        let mut set = FastSet::new(10).unwrap();
+        let err = set.merge_sorted_run(&[4, 12, 0, 5]).unwrap_err();
+        assert_eq!((err.cap, err.key), (10, 12));
+        assert_eq!(set.to_sorted_vec(), [4]);
+    }
+
+    #[test]
+    fn partitions_with() {
+        let evens = FastSet::from_stride(6, 0, 2).unwrap();
+        let odds = FastSet::from_stride(6, 1, 2).unwrap();
+        assert!(evens.is_disjoint(&odds));
+        assert!(evens.partitions_with(&odds, 6));
+        assert!(odds.partitions_with(&evens, 6));
+        assert!(!evens.partitions_with(&odds, 7));
+
+        let gapped = FastSet::from_sorted_slice(6, &[1, 3]).unwrap();
+        assert!(!evens.partitions_with(&gapped, 6));
+        let overlapping = FastSet::from_sorted_slice(6, &[0, 1, 3, 5]).unwrap();
+        assert!(!evens.is_disjoint(&overlapping));
+        assert!(!gapped.partitions_with(&overlapping, 6));
+
+        let big = FastSet::from_sorted_slice(10, &[1, 3, 9]).unwrap();
+        let rest = FastSet::from_sorted_slice(3, &[0, 2]).unwrap();
+        assert!(!big.partitions_with(&rest, 5));
+        assert!(!big.partitions_with(&rest, 4));
+    }
+
+    #[test]
+    fn iter_sorted_with_boundaries() {
+        let mut set = FastSet::new(10).unwrap();
+        set.toggle_all([6, 1, 4, 5, 3]).unwrap();
+        assert_eq!(
+            set.iter_sorted_with_boundaries().collect::<Vec<_>>(),
+            [
+                (1, true, true),
+                (3, true, false),
+                (4, false, false),
+                (5, false, false),
+                (6, false, true),
+            ]
+        );
+        assert_eq!(
+            FastSet::new(3)
+                .unwrap()
+                .iter_sorted_with_boundaries()
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn all_disjoint() {
+        let a = FastSet::from_sorted_slice(8, &[0, 3]).unwrap();
+        let b = FastSet::from_sorted_slice(8, &[1, 4, 7]).unwrap();
+        let c = FastSet::from_sorted_slice(6, &[2, 5]).unwrap();
+        let d = FastSet::from_sorted_slice(8, &[5, 6]).unwrap();
+        assert!(FastSet::all_disjoint([&a, &b, &c], 8));
+        assert!(!FastSet::all_disjoint([&a, &b, &c, &d], 8));
+        assert!(FastSet::all_disjoint([], 8));
+
+        let mut scratch = FastSet::new(8).unwrap();
+        assert!(!FastSet::all_disjoint_with([&c, &d], &mut scratch));
+        assert!(FastSet::all_disjoint_with([&a, &d], &mut scratch));
+        assert_eq!(scratch.to_sorted_vec(), [0, 3, 5, 6]);
+        // Keys beyond the scratch capacity are ignored
+        assert!(FastSet::all_disjoint_with(
+            [&b, &b],
+            &mut FastSet::new(1).unwrap()
+        ));
+    }
+
+    #[test]
+    fn insertion_positions() {
+        let mut set = FastSet::new(10).unwrap();
+        set.toggle_all([7, 2, 5, 9]).unwrap();
+        assert_eq!(set.insertion_positions(5), Some(2));
+        assert_eq!(set.insertion_positions(9), Some(3));
+        assert_eq!(set.insertion_positions(3), None);
+        assert_eq!(set.insertion_positions(100), None);
+        set.remove(2).unwrap();
+        assert_eq!(set.insertion_positions(9), Some(1));
+        assert_eq!(set.insertion_positions(2), None);
+        for (i, &key) in set.keys().iter().enumerate() {
+            assert_eq!(set.insertion_positions(key), Some(i));
         }
-        let other = set.clone();
+    }
+
+    #[test]
+    fn from_threshold() {
+        let scores = [0.5, 0.9, f64::NAN, 0.7, 0.2];
+        let set = FastSet::from_threshold(&scores, 0.7).unwrap();
+        assert_eq!(set.cap(), 5);
+        assert_eq!(set.to_sorted_vec(), [1, 3]);
+        assert_eq!(FastSet::from_threshold(&scores, 0.0).unwrap().len(), 4);
+        assert!(FastSet::from_threshold(&scores, 1.0).unwrap().is_empty());
+        let all = FastSet::from_threshold(&[1.0, 2.0], f64::NEG_INFINITY).unwrap();
+        assert_eq!(all.to_sorted_vec(), [0, 1]);
+
+        let words = ["a", "bcd", "", "ef"];
+        let set = FastSet::from_threshold_by(&words, |word| word.len() >= 2).unwrap();
+        assert_eq!(set.to_sorted_vec(), [1, 3]);
+    }
+
+    #[test]
+    fn shrink_if_sparse() {
+        let mut set = FastSet::new(10).unwrap();
+        set.toggle_all([1, 6]).unwrap();
+        set.shrink_if_sparse(0.5);
+        assert_eq!(set.cap(), 10);
+        set.shrink_if_sparse(0.25);
+        assert_eq!(set.cap(), 7);
+        assert_eq!(set.to_sorted_vec(), [1, 6]);
+        set.shrink_if_sparse(0.0);
+        assert_eq!(set.cap(), 7);
+
         set.clear();
-        assert!(!set.contains(5));
-        assert!(other.contains(5));
+        set.shrink_if_sparse(0.99);
+        assert_eq!(set.cap(), 0);
+        set.shrink_if_sparse(0.0);
+        assert_eq!(set.cap(), 0);
+
+        let mut set = FastSet::from_sorted_slice(5, &[0, 2]).unwrap();
+        set.shrink_to_fit();
+        assert_eq!(set.cap(), 3);
+        set.add(1).unwrap();
+        assert_eq!(set.to_sorted_vec(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn tagged_union() {
+        let mut set = FastSet::new(6).unwrap();
+        set.toggle_all([4, 1, 2]).unwrap();
+        let mut other = FastSet::new(9).unwrap();
+        other.toggle_all([8, 2, 0, 4]).unwrap();
+        let mut tagged: Vec<_> = set.tagged_union(&other).collect();
+        tagged.sort_unstable_by_key(|&(key, _)| key);
+        assert_eq!(
+            tagged,
+            [
+                (0, Source::OtherOnly),
+                (1, Source::SelfOnly),
+                (2, Source::Both),
+                (4, Source::Both),
+                (8, Source::OtherOnly),
+            ]
+        );
+    }
+
+    #[test]
+    fn compact_pool() {
+        let mut pool = vec![
+            FastSet::from_sorted_slice(100, &[3, 40]).unwrap(),
+            FastSet::new(50).unwrap(),
+            FastSet::from_sorted_slice(5, &[4]).unwrap(),
+        ];
+        FastSet::compact_pool(&mut pool);
+        let caps: Vec<_> = pool.iter().map(FastSet::cap).collect();
+        assert_eq!(caps, [41, 0, 5]);
+        assert_eq!(pool[0].to_sorted_vec(), [3, 40]);
+        assert!(pool[1].is_empty());
+        assert_eq!(pool[2].to_sorted_vec(), [4]);
+    }
+
+    #[test]
+    fn would_insert() {
+        let mut set = FastSet::new(5).unwrap();
+        set.add(2).unwrap();
+        assert!(set.would_insert(3));
+        assert!(!set.would_insert(2));
+        assert!(!set.would_insert(5));
+        assert!(!set.would_insert(usize::MAX));
+    }
+
+    #[test]
+    fn iter_rev_insertion() {
+        let mut set = FastSet::new(10).unwrap();
+        set.toggle_all([3, 8, 1, 6]).unwrap();
+        assert_eq!(set.iter_rev_insertion().collect::<Vec<_>>(), [6, 1, 8, 3]);
+        set.remove(8).unwrap();
+        assert_eq!(set.iter_rev_insertion().collect::<Vec<_>>(), [1, 6, 3]);
+    }
+
+    #[test]
+    fn histogram() {
+        let all = FastSet::from_stride(12, 0, 1).unwrap();
+        assert_eq!(all.histogram(4), [3, 3, 3, 3]);
+        assert_eq!(all.histogram(1), [12]);
+        // Boundaries 0, 3, 6, 10
+        let all = FastSet::from_stride(10, 0, 1).unwrap();
+        assert_eq!(all.histogram(3), [3, 3, 4]);
+        assert_eq!(all.histogram(20).iter().sum::<usize>(), 10);
+
+        let set = FastSet::from_sorted_slice(10, &[2, 3, 5, 6, 9]).unwrap();
+        assert_eq!(set.histogram(3), [1, 2, 2]);
+        assert_eq!(FastSet::new(0).unwrap().histogram(2), [0, 0]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn histogram_zero() {
+        FastSet::new(4).unwrap().histogram(0);
+    }
+
+    #[test]
+    fn push() {
+        let mut set = FastSet::new(5).unwrap();
+        assert!(set.push(3).unwrap());
+        assert!(set.push(1).unwrap());
+        assert!(!set.push(3).unwrap());
+        assert_eq!(set.keys(), [3, 1]);
+        assert!(set.push(5).is_err());
+    }
+
+    #[test]
+    fn logically_equal() {
+        let mut a = FastSet::new(8).unwrap();
+        a.toggle_all([1, 5, 3]).unwrap();
+        let mut b = FastSet::new(8).unwrap();
+        b.toggle_all([3, 1, 5]).unwrap();
+        assert!(a.logically_equal(&b) && b.logically_equal(&a));
+
+        // Same keys, different cap
+        let c = FastSet::from_sorted_slice(9, &[1, 3, 5]).unwrap();
+        assert!(!a.logically_equal(&c));
+        assert!(a.len() == c.len() && a.is_subset(&c));
+
+        b.toggle(7).unwrap();
+        assert!(!a.logically_equal(&b) && !b.logically_equal(&a));
+        b.toggle_all([7, 5, 6]).unwrap();
+        assert!(!a.logically_equal(&b));
+        assert!(FastSet::new(0)
+            .unwrap()
+            .logically_equal(&FastSet::new(0).unwrap()));
+    }
+
+    #[test]
+    fn apply_commands() {
+        use SetCommand::*;
+        let mut set = FastSet::new(8).unwrap();
+        set.apply_commands([Add(1), Add(4), Toggle(6), Remove(1), Toggle(4), Add(2)])
+            .unwrap();
+        assert_eq!(set.to_sorted_vec(), [2, 6]);
+        set.apply_commands([Clear, Add(7)]).unwrap();
+        assert_eq!(set.to_sorted_vec(), [7]);
+
+        let err = set.apply_commands([Add(3), Toggle(8), Add(5)]).unwrap_err();
+        assert_eq!((err.cap, err.key), (8, 8));
+        assert_eq!(set.to_sorted_vec(), [3, 7]);
+    }
+
+    #[test]
+    fn with_recorder() {
+        use SetCommand::*;
+        let mut set = FastSet::with_recorder(10).unwrap();
+        set.toggle_all([3, 7, 1]).unwrap();
+        set.toggle(7).unwrap();
+        set.retain(|key| key != 1);
+        set.add(3).unwrap();
+        assert_eq!(
+            set.take_log(),
+            [Add(3), Add(7), Add(1), Remove(7), Remove(1)]
+        );
+        assert!(set.take_log().is_empty());
+
+        let before = set.set().clone();
+        set.clear();
+        set.toggle_all([9, 0, 4]).unwrap();
+        set.remove(0).unwrap();
+        set.remove(0).unwrap();
+        let log = set.take_log();
+        assert_eq!(log, [Clear, Add(9), Add(0), Add(4), Remove(0)]);
+        let mut replay = before.clone();
+        replay.apply_commands(log).unwrap();
+        assert!(replay.logically_equal(set.set()));
+
+        assert!(set.add(10).is_err());
+        assert!(set.take_log().is_empty());
+        assert_eq!(set.into_inner().to_sorted_vec(), [4, 9]);
+    }
+
+    #[test]
+    fn downsample() {
+        let set = FastSet::from_sorted_slice(10, &[0, 4, 5, 6, 7, 9]).unwrap();
+        let coarse = set.downsample(4).unwrap();
+        assert_eq!(coarse.cap(), 3);
+        assert_eq!(coarse.to_sorted_vec(), [0, 1, 2]);
+        let coarse = set.downsample(3).unwrap();
+        assert_eq!(coarse.cap(), 4);
+        assert_eq!(coarse.to_sorted_vec(), [0, 1, 2, 3]);
+        let cluster = FastSet::from_sorted_slice(100, &[40, 41, 47]).unwrap();
+        assert_eq!(cluster.downsample(10).unwrap().to_sorted_vec(), [4]);
+        assert_eq!(
+            set.downsample(1).unwrap().to_sorted_vec(),
+            set.to_sorted_vec()
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn downsample_zero() {
+        FastSet::new(4).unwrap().downsample(0).unwrap();
+    }
+
+    #[test]
+    fn upsample() {
+        let set = FastSet::from_sorted_slice(4, &[1, 3]).unwrap();
+        let fine = set.upsample(3).unwrap();
+        assert_eq!(fine.cap(), 12);
+        assert_eq!(fine.to_sorted_vec(), [3, 4, 5, 9, 10, 11]);
+        assert_eq!(fine.downsample(3).unwrap().to_sorted_vec(), [1, 3]);
+        assert_eq!(set.upsample(0).unwrap().cap(), 0);
+        assert!(set.upsample(usize::MAX / 2).is_err());
+        assert!(set.upsample(isize::MAX as usize).is_err());
+    }
+
+    #[test]
+    fn shifted() {
+        let set = FastSet::from_sorted_slice(10, &[0, 2, 7, 9]).unwrap();
+        let right = set.shifted(2).unwrap();
+        assert_eq!(right.cap(), 10);
+        assert_eq!(right.to_sorted_vec(), [2, 4, 9]);
+        assert_eq!(set.shifted(-2).unwrap().to_sorted_vec(), [0, 5, 7]);
+        assert_eq!(set.shifted(0).unwrap().to_sorted_vec(), [0, 2, 7, 9]);
+        assert!(set.shifted(10).unwrap().is_empty());
+        assert!(set.shifted(-10).unwrap().is_empty());
+        assert!(set.shifted(isize::MAX).unwrap().is_empty());
+        assert!(set.shifted(isize::MIN).unwrap().is_empty());
+    }
+
+    #[test]
+    fn equal_under_shift() {
+        let set = FastSet::from_sorted_slice(10, &[2, 3, 7]).unwrap();
+        let moved = FastSet::from_sorted_slice(20, &[10, 11, 15]).unwrap();
+        assert_eq!(set.equal_under_shift(&moved), Some(8));
+        assert_eq!(moved.equal_under_shift(&set), Some(-8));
+        assert_eq!(set.equal_under_shift(&set), Some(0));
+
+        let bent = FastSet::from_sorted_slice(20, &[10, 11, 16]).unwrap();
+        assert_eq!(set.equal_under_shift(&bent), None);
+        let longer = FastSet::from_sorted_slice(20, &[10, 11, 15, 16]).unwrap();
+        assert_eq!(set.equal_under_shift(&longer), None);
+        let empty = FastSet::new(3).unwrap();
+        assert_eq!(empty.equal_under_shift(&FastSet::new(5).unwrap()), Some(0));
+        assert_eq!(empty.equal_under_shift(&set), None);
+    }
+
+    #[test]
+    fn self_overlap() {
+        let set = FastSet::from_stride(20, 1, 4).unwrap();
+        let overlaps: Vec<_> = (0..9).map(|lag| set.self_overlap(lag)).collect();
+        assert_eq!(overlaps, [5, 0, 0, 0, 4, 0, 0, 0, 3]);
+        assert_eq!(set.self_overlap(usize::MAX), 0);
+        assert_eq!(FastSet::new(3).unwrap().self_overlap(0), 0);
+    }
+
+    #[test]
+    fn top_k_indices() {
+        let scores = [0.3, 0.9, 0.1, 0.5, 0.9, 0.7];
+        let top = FastSet::top_k_indices(&scores, 3).unwrap();
+        assert_eq!(top.cap(), 6);
+        assert_eq!(top.to_sorted_vec(), [1, 4, 5]);
+        let top = FastSet::top_k_indices(&scores, 1).unwrap();
+        assert_eq!(top.to_sorted_vec(), [1]);
+        assert!(FastSet::top_k_indices(&scores, 0).unwrap().is_empty());
+        let all = FastSet::top_k_indices(&scores, 10).unwrap();
+        assert_eq!(all.to_sorted_vec(), [0, 1, 2, 3, 4, 5]);
+        assert!(FastSet::top_k_indices(&[], 2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn set_expr() {
+        let a = FastSet::from_sorted_slice(10, &[1, 2, 3]).unwrap();
+        let b = FastSet::from_sorted_slice(12, &[3, 4, 5, 11]).unwrap();
+        let c = FastSet::from_sorted_slice(8, &[2, 3, 4, 5, 6]).unwrap();
+        let d = FastSet::from_sorted_slice(10, &[4]).unwrap();
+        let expr = SetExpr::from(&a).union(&b).intersect(&c).difference(&d);
+        assert_eq!(expr.cap(), 8);
+        assert!(expr.contains(2) && expr.contains(5));
+        assert!(!expr.contains(1) && !expr.contains(4) && !expr.contains(11));
+        let result = expr.evaluate();
+        assert_eq!(result.cap(), 8);
+        assert_eq!(result.to_sorted_vec(), [2, 3, 5]);
+
+        let union = SetExpr::from(&a).union(&b).evaluate();
+        assert_eq!(union.cap(), 12);
+        assert_eq!(union.to_sorted_vec(), [1, 2, 3, 4, 5, 11]);
+    }
+
+    #[test]
+    fn count_in_ranges() {
+        let set = FastSet::from_sorted_slice(20, &[1, 2, 5, 8, 9, 10, 15, 19]).unwrap();
+        assert_eq!(set.count_in_range(0..10), 5);
+        assert_eq!(set.count_in_range(2..3), 1);
+        assert_eq!(set.count_in_range(15..100), 2);
+        assert_eq!(set.count_in_range(3..3), 0);
+        assert_eq!(
+            set.count_in_ranges(&[0..3, 3..3, 5..9, 12..100]),
+            [2, 0, 2, 2]
+        );
+        assert_eq!(set.count_in_ranges(&[0..10, 8..16, 1..2]), [5, 4, 1]);
+        assert!(set.count_in_ranges(&[]).is_empty());
+    }
+
+    #[test]
+    fn membership_prefix_sum() {
+        let set = FastSet::from_sorted_slice(12, &[0, 3, 4, 9]).unwrap();
+        let ranks = set.membership_prefix_sum();
+        assert_eq!(ranks.len(), 13);
+        assert_eq!(ranks[12], set.len());
+        for (i, &rank) in ranks.iter().enumerate() {
+            assert_eq!(rank, set.keys().iter().filter(|&&key| key < i).count());
+        }
+        assert_eq!(FastSet::new(0).unwrap().membership_prefix_sum(), [0]);
+    }
+
+    #[test]
+    fn select() {
+        let mut set = FastSet::new(20).unwrap();
+        set.toggle_all([12, 3, 17, 8, 5]).unwrap();
+        assert_eq!(set.select(0), set.min());
+        assert_eq!(set.select(set.len() - 1), set.max());
+        let selected: Vec<_> = (0..5).map(|n| set.select(n).unwrap()).collect();
+        assert_eq!(selected, set.to_sorted_vec());
+        assert_eq!(set.select(5), None);
+        assert_eq!(FastSet::new(3).unwrap().select(0), None);
+    }
+
+    #[test]
+    fn rename() {
+        let mut set = FastSet::from_sorted_slice(8, &[1, 4, 6]).unwrap();
+        assert!(!set.rename(2, 3).unwrap());
+        assert_eq!(set.to_sorted_vec(), [1, 4, 6]);
+        assert!(set.rename(4, 7).unwrap());
+        assert_eq!(set.to_sorted_vec(), [1, 6, 7]);
+        assert!(set.rename(1, 6).unwrap());
+        assert_eq!(set.to_sorted_vec(), [6, 7]);
+        assert!(!set.rename(6, 6).unwrap());
+        assert_eq!(set.rename(6, 8).unwrap_err().key, 8);
+        assert_eq!(set.rename(9, 6).unwrap_err().key, 9);
+        assert_eq!(set.to_sorted_vec(), [6, 7]);
+    }
+
+    #[test]
+    fn fold_modulo() {
+        let set = FastSet::from_sorted_slice(30, &[1, 8, 11, 21, 24]).unwrap();
+        let residues = set.fold_modulo(10).unwrap();
+        assert_eq!(residues.cap(), 10);
+        assert_eq!(residues.to_sorted_vec(), [1, 4, 8]);
+        assert_eq!(set.fold_modulo(1).unwrap().to_sorted_vec(), [0]);
+        assert_eq!(
+            set.fold_modulo(50).unwrap().to_sorted_vec(),
+            set.to_sorted_vec()
+        );
+        assert!(set.fold_modulo(usize::MAX).is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn fold_modulo_zero() {
+        FastSet::new(4).unwrap().fold_modulo(0).unwrap();
+    }
+
+    #[test]
+    fn is_successor_closed() {
+        let set = FastSet::from_stride(10, 2, 3).unwrap();
+        assert!(set.is_successor_closed(3));
+        assert!(set.is_successor_closed(6));
+        assert!(!set.is_successor_closed(1));
+        assert!(set.is_successor_closed(0));
+        assert!(set.is_successor_closed(usize::MAX));
+
+        let tail = FastSet::from_sorted_slice(10, &[7, 8, 9]).unwrap();
+        assert!(tail.is_successor_closed(1));
+        let gapped = FastSet::from_sorted_slice(10, &[2, 5, 9]).unwrap();
+        assert!(!gapped.is_successor_closed(3));
+    }
+
+    #[test]
+    fn delta_varint() {
+        let mut set = FastSet::new(1000).unwrap();
+        set.toggle_all([300, 1, 5, 999]).unwrap();
+        let bytes = set.to_delta_varint();
+        // cap 1000, len 4, then deltas 1, 4, 295, 699
+        assert_eq!(bytes, [0xe8, 0x07, 4, 1, 4, 0xa7, 0x02, 0xbb, 0x05]);
+        let decoded = FastSet::from_delta_varint(&bytes).unwrap();
+        assert!(decoded.logically_equal(&set));
+        for set in [
+            FastSet::new(0).unwrap(),
+            FastSet::from_stride(50, 0, 1).unwrap(),
+        ] {
+            let decoded = FastSet::from_delta_varint(&set.to_delta_varint()).unwrap();
+            assert!(decoded.logically_equal(&set));
+        }
+
+        let decode = |bytes: &[u8]| FastSet::from_delta_varint(bytes).err().unwrap();
+        assert!(matches!(decode(&[]), DecodeError::UnexpectedEnd));
+        assert!(matches!(decode(&bytes[..6]), DecodeError::UnexpectedEnd));
+        assert!(matches!(decode(&[10, 0x80]), DecodeError::UnexpectedEnd));
+        assert!(matches!(
+            decode(&[10, 200, 1, 1]),
+            DecodeError::UnexpectedEnd
+        ));
+        assert!(matches!(decode(&[10, 1, 3, 0]), DecodeError::TrailingBytes));
+        assert!(matches!(
+            decode(&[10, 2, 3, 0]),
+            DecodeError::NotIncreasing { key: 3 }
+        ));
+        assert!(matches!(
+            decode(&[10, 2, 3, 7]),
+            DecodeError::OutOfBounds(OutOfBounds { cap: 10, key: 10 })
+        ));
+        assert!(matches!(decode(&[0xff; 11]), DecodeError::Overflow));
+        let mut huge = Vec::new();
+        write_varint(&mut huge, usize::MAX);
+        huge.push(0);
+        assert!(matches!(decode(&huge), DecodeError::Layout(_)));
+    }
+
+    #[test]
+    fn relate() {
+        let a = FastSet::from_sorted_slice(10, &[1, 2, 3]).unwrap();
+        let b = FastSet::from_sorted_slice(5, &[1, 2, 3]).unwrap();
+        let c = FastSet::from_sorted_slice(10, &[1, 2, 3, 7]).unwrap();
+        let d = FastSet::from_sorted_slice(10, &[5, 6]).unwrap();
+        let e = FastSet::from_sorted_slice(10, &[3, 4]).unwrap();
+        let empty = FastSet::new(10).unwrap();
+        assert_eq!(a.relate(&b), Relation::Equal);
+        assert_eq!(a.relate(&c), Relation::Subset);
+        assert_eq!(c.relate(&a), Relation::Superset);
+        assert_eq!(a.relate(&d), Relation::Disjoint);
+        assert_eq!(a.relate(&e), Relation::Overlapping);
+        assert_eq!(empty.relate(&FastSet::new(0).unwrap()), Relation::Equal);
+        assert_eq!(empty.relate(&a), Relation::Subset);
+        assert_eq!(a.relate(&empty), Relation::Superset);
+    }
+
+    #[test]
+    fn gap_sequence() {
+        let mut set = FastSet::new(20).unwrap();
+        set.toggle_all([9, 1, 4, 5, 17]).unwrap();
+        assert_eq!(set.gap_sequence(), [3, 1, 4, 8]);
+        assert!(FastSet::from_sorted_slice(3, &[2])
+            .unwrap()
+            .gap_sequence()
+            .is_empty());
+    }
+
+    #[test]
+    fn map_keys() {
+        let set = FastSet::from_sorted_slice(10, &[1, 4, 6, 9]).unwrap();
+        let halves = set.map_keys(5, |key| key / 2).unwrap();
+        assert_eq!(halves.cap(), 5);
+        assert_eq!(halves.to_sorted_vec(), [0, 2, 3, 4]);
+        let err = set.map_keys(10, |key| key + 1).unwrap_err();
+        assert_eq!((err.cap, err.key), (10, 10));
+        assert_eq!(set.map_keys(1, |_| 0).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn dilate() {
+        let set = FastSet::from_sorted_slice(10, &[1, 5, 6, 9]).unwrap();
+        let wide = set.dilate(&[0, 1], 10).unwrap();
+        assert_eq!(wide.cap(), 10);
+        assert_eq!(wide.to_sorted_vec(), [1, 2, 5, 6, 7, 9]);
+        let wide = set.dilate(&[0, 1], 12).unwrap();
+        assert_eq!(wide.to_sorted_vec(), [1, 2, 5, 6, 7, 9, 10]);
+        assert_eq!(
+            set.dilate(&[3, usize::MAX], 10).unwrap().to_sorted_vec(),
+            [4, 8, 9]
+        );
+        assert!(set.dilate(&[], 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn erode() {
+        let set = FastSet::from_sorted_slice(12, &[1, 2, 3, 4, 5, 8, 10, 11]).unwrap();
+        let narrow = set.erode(&[0, 1]);
+        assert_eq!(narrow.cap(), 12);
+        assert_eq!(narrow.to_sorted_vec(), [1, 2, 3, 4, 10]);
+        assert_eq!(set.erode(&[0, 2]).to_sorted_vec(), [1, 2, 3, 8]);
+        // 11 + 1 is past the capacity, so 11 goes too
+        assert_eq!(set.erode(&[1]).to_sorted_vec(), [1, 2, 3, 4, 10]);
+        assert_eq!(set.erode(&[]).to_sorted_vec(), set.to_sorted_vec());
+        assert!(set.erode(&[usize::MAX]).is_empty());
+    }
+
+    #[test]
+    fn is_complement_of() {
+        let set = FastSet::from_sorted_slice(6, &[0, 2, 3]).unwrap();
+        let rest = FastSet::from_sorted_slice(6, &[1, 4, 5]).unwrap();
+        assert!(set.is_complement_of(&rest) && rest.is_complement_of(&set));
+        let overlapping = FastSet::from_sorted_slice(6, &[1, 2, 5]).unwrap();
+        assert!(!set.is_complement_of(&overlapping));
+        let short = FastSet::from_sorted_slice(6, &[1, 4]).unwrap();
+        assert!(!set.is_complement_of(&short));
+        let wider = FastSet::from_sorted_slice(7, &[1, 4, 5]).unwrap();
+        assert!(!set.is_complement_of(&wider));
+        assert!(FastSet::new(0)
+            .unwrap()
+            .is_complement_of(&FastSet::new(0).unwrap()));
+    }
+
+    #[test]
+    fn diff_hash() {
+        let a = FastSet::from_sorted_slice(10, &[1, 2, 3]).unwrap();
+        let b = FastSet::from_sorted_slice(12, &[2, 3, 11]).unwrap();
+        assert_eq!(a.diff_hash(&b), b.diff_hash(&a));
+        assert_eq!(a.diff_hash(&b), a.checksum() ^ b.checksum());
+        assert_eq!(a.diff_hash(&a), 0);
+
+        // The same change applied to a different base hashes the same
+        let c = FastSet::from_sorted_slice(12, &[1, 5]).unwrap();
+        let d = FastSet::from_sorted_slice(12, &[5, 11]).unwrap();
+        assert_eq!(c.diff_hash(&d), a.diff_hash(&b));
+        assert_ne!(a.diff_hash(&c), a.diff_hash(&b));
+    }
+
+    #[test]
+    fn from_mask_iter() {
+        let set = FastSet::from_mask_iter(130, [0b1010, 1 << 63, 0b11]).unwrap();
+        assert_eq!(set.cap(), 130);
+        assert_eq!(set.to_sorted_vec(), [1, 3, 127, 128, 129]);
+        let short = FastSet::from_mask_iter(200, [0b1]).unwrap();
+        assert_eq!(short.to_sorted_vec(), [0]);
+        assert!(FastSet::from_mask_iter(70, [0, 0, 0]).unwrap().is_empty());
+
+        let err = FastSet::from_mask_iter(66, [1, 0b1101]).err().unwrap();
+        assert_eq!((err.cap, err.key), (66, 66));
+        let err = FastSet::from_mask_iter(64, [1, 0, 1 << 5]).err().unwrap();
+        assert_eq!(err.key, 133);
+    }
+
+    #[test]
+    fn snap_to_grid() {
+        let set = FastSet::from_sorted_slice(25, &[3, 5, 9, 10, 14, 24]).unwrap();
+        let snapped = set.snap_to_grid(5).unwrap();
+        assert_eq!(snapped.cap(), 25);
+        assert_eq!(snapped.to_sorted_vec(), [0, 5, 10, 20]);
+        assert_eq!(
+            set.snap_to_grid(1).unwrap().to_sorted_vec(),
+            set.to_sorted_vec()
+        );
+        assert_eq!(set.snap_to_grid(100).unwrap().to_sorted_vec(), [0]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn snap_to_grid_zero() {
+        FastSet::new(4).unwrap().snap_to_grid(0).unwrap();
+    }
+
+    #[test]
+    fn is_monotone_under() {
+        let order = [0, 5, 1, 5, 2, 9];
+        let set = FastSet::from_sorted_slice(6, &[0, 2, 4, 5]).unwrap();
+        assert!(set.is_monotone_under(|key| order[key]));
+        let set = FastSet::from_sorted_slice(6, &[1, 3, 5]).unwrap();
+        assert!(set.is_monotone_under(|key| order[key]));
+        let set = FastSet::from_sorted_slice(6, &[1, 2]).unwrap();
+        assert!(!set.is_monotone_under(|key| order[key]));
+        assert!(FastSet::new(3).unwrap().is_monotone_under(|key| 10 - key));
+    }
+
+    #[test]
+    fn reflect() {
+        let set = FastSet::from_sorted_slice(10, &[0, 3, 4, 8]).unwrap();
+        let mirrored = set.reflect(5);
+        assert_eq!(mirrored.cap(), 10);
+        assert_eq!(mirrored.to_sorted_vec(), [2, 6, 7]);
+        assert_eq!(mirrored.reflect(5).to_sorted_vec(), [3, 4, 8]);
+        assert_eq!(set.reflect(2).to_sorted_vec(), [0, 1, 4]);
+        assert!(set.reflect(usize::MAX).is_empty());
+    }
+
+    #[test]
+    fn is_symmetric_about() {
+        let set = FastSet::from_sorted_slice(10, &[2, 4, 5, 6, 8]).unwrap();
+        assert!(set.is_symmetric_about(5));
+        assert!(!set.is_symmetric_about(4));
+        let set = FastSet::from_sorted_slice(10, &[3, 5, 9]).unwrap();
+        assert!(!set.is_symmetric_about(6));
+        // 7's partner would be 11, past the capacity
+        let set = FastSet::from_sorted_slice(10, &[7, 9]).unwrap();
+        assert!(!set.is_symmetric_about(9));
+        assert!(FastSet::new(4).unwrap().is_symmetric_about(100));
+    }
+
+    #[test]
+    fn component_count() {
+        let set = FastSet::from_sorted_slice(20, &[1, 2, 3, 5, 6, 9, 15, 16]).unwrap();
+        assert_eq!(set.component_count(1), set.to_runs().len());
+        assert_eq!(set.component_count(1), 4);
+        assert_eq!(set.component_count(2), 3);
+        assert_eq!(set.component_count(3), 2);
+        assert_eq!(set.component_count(6), 1);
+        assert_eq!(set.component_count(0), set.len());
+        assert_eq!(FastSet::new(5).unwrap().component_count(1), 0);
+    }
+
+    #[test]
+    fn component_of() {
+        let set = FastSet::from_sorted_slice(20, &[1, 2, 3, 5, 6, 9, 15, 16]).unwrap();
+        let component = set.component_of(5, 1).unwrap();
+        assert_eq!(component.cap(), 20);
+        assert_eq!(component.to_sorted_vec(), [5, 6]);
+        assert_eq!(
+            set.component_of(5, 2).unwrap().to_sorted_vec(),
+            [1, 2, 3, 5, 6]
+        );
+        assert_eq!(
+            set.component_of(1, 3).unwrap().to_sorted_vec(),
+            [1, 2, 3, 5, 6, 9]
+        );
+        assert_eq!(set.component_of(16, 3).unwrap().to_sorted_vec(), [15, 16]);
+        assert_eq!(set.component_of(9, 0).unwrap().to_sorted_vec(), [9]);
+        assert!(set.component_of(4, 2).is_none());
+        assert!(set.component_of(100, 2).is_none());
+    }
+
+    #[test]
+    fn weighted_mean() {
+        let weights = [5.0, 1.0, 0.0, 3.0, 2.0];
+        let set = FastSet::from_sorted_slice(5, &[1, 3, 4]).unwrap();
+        // (1 * 1 + 3 * 3 + 4 * 2) / (1 + 3 + 2)
+        assert_eq!(set.weighted_mean(&weights), Some(3.0));
+        let set = FastSet::from_sorted_slice(5, &[0, 2]).unwrap();
+        assert_eq!(set.weighted_mean(&weights), Some(0.0));
+        let set = FastSet::from_sorted_slice(5, &[2]).unwrap();
+        assert_eq!(set.weighted_mean(&weights), None);
+        assert_eq!(FastSet::new(5).unwrap().weighted_mean(&weights), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn weighted_mean_short_weights() {
+        FastSet::new(4).unwrap().weighted_mean(&[1.0; 3]);
+    }
+
+    #[test]
+    fn overlap_matrix() {
+        let sets = [
+            FastSet::from_sorted_slice(10, &[1, 2, 3, 4]).unwrap(),
+            FastSet::from_sorted_slice(8, &[3, 4, 5]).unwrap(),
+            FastSet::from_sorted_slice(10, &[4, 5, 6, 7, 8]).unwrap(),
+        ];
+        assert_eq!(
+            FastSet::overlap_matrix(&sets),
+            [vec![4, 2, 1], vec![2, 3, 2], vec![1, 2, 5]]
+        );
+        assert!(FastSet::overlap_matrix(&[]).is_empty());
+    }
+
+    #[test]
+    fn union_accumulator() {
+        let sets = [
+            FastSet::from_sorted_slice(5, &[0, 3]).unwrap(),
+            FastSet::from_sorted_slice(12, &[3, 7, 11]).unwrap(),
+            FastSet::from_sorted_slice(8, &[1, 7]).unwrap(),
+        ];
+        let mut acc = UnionAccumulator::new(2).unwrap();
+        for set in &sets {
+            acc.add_set(set);
+        }
+        let union = acc.finish();
+        assert_eq!(union.cap(), 12);
+        let mut expected = FastSet::new(12).unwrap();
+        FastSet::union_len_many_with(&sets, &mut expected);
+        assert!(union.logically_equal(&expected));
+        assert_eq!(union.to_sorted_vec(), [0, 1, 3, 7, 11]);
+        assert_eq!(UnionAccumulator::new(4).unwrap().finish().cap(), 4);
+    }
+
+    #[test]
+    fn without_stride() {
+        let set = FastSet::from_stride(12, 0, 1).unwrap();
+        let kept = set.without_stride(2, 3);
+        assert_eq!(kept.cap(), 12);
+        assert_eq!(kept.to_sorted_vec(), [0, 1, 3, 4, 6, 7, 9, 10]);
+        let set = FastSet::from_sorted_slice(12, &[1, 4, 5, 9, 11]).unwrap();
+        assert_eq!(set.without_stride(5, 2).to_sorted_vec(), [1, 4]);
+        assert_eq!(
+            set.without_stride(100, 1).to_sorted_vec(),
+            set.to_sorted_vec()
+        );
+        assert_eq!(
+            set.without_stride(4, 0).to_sorted_vec(),
+            set.to_sorted_vec()
+        );
+    }
+
+    #[test]
+    fn len_after() {
+        let set = FastSet::from_sorted_slice(5, &[1, 3]).unwrap();
+        assert_eq!(set.len_after_add(0), 3);
+        assert_eq!(set.len_after_add(3), 2);
+        assert_eq!(set.len_after_add(5), 2);
+        assert_eq!(set.len_after_remove(3), 1);
+        assert_eq!(set.len_after_remove(0), 2);
+        assert_eq!(set.len_after_remove(usize::MAX), 2);
+    }
+
+    #[test]
+    fn diff_indices() {
+        let a = [1, 2, 3, 4, 5];
+        let b = [1, 0, 3, 4, 6];
+        let changed = FastSet::diff_indices(&a, &b).unwrap();
+        assert_eq!(changed.cap(), 5);
+        assert_eq!(changed.to_sorted_vec(), [1, 4]);
+        let longer = FastSet::diff_indices(&a, &[1, 2, 9, 4, 5, 6, 7]).unwrap();
+        assert_eq!(longer.cap(), 7);
+        assert_eq!(longer.to_sorted_vec(), [2, 5, 6]);
+        let shorter = FastSet::diff_indices(&a, &a[..3]).unwrap();
+        assert_eq!(shorter.to_sorted_vec(), [3, 4]);
+        assert!(FastSet::diff_indices(&a, &a).unwrap().is_empty());
+    }
+
+    #[test]
+    fn local_maxima() {
+        let signal = [3.0, 1.0, 4.0, 1.0, 5.0, 5.0, 2.0, 6.0];
+        let peaks = FastSet::local_maxima(&signal).unwrap();
+        assert_eq!(peaks.cap(), 8);
+        assert_eq!(peaks.to_sorted_vec(), [0, 2, 7]);
+        assert_eq!(FastSet::local_maxima(&[2.0]).unwrap().to_sorted_vec(), [0]);
+        assert!(FastSet::local_maxima(&[1.0, 1.0]).unwrap().is_empty());
+        let with_nan = FastSet::local_maxima(&[0.0, f64::NAN, 2.0, 1.0]).unwrap();
+        assert!(with_nan.is_empty());
+        assert!(FastSet::local_maxima(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn would_exceed_density() {
+        let set = FastSet::from_sorted_slice(10, &[0, 1, 2]).unwrap();
+        assert!(!set.would_exceed_density(&[], 0.3));
+        assert!(set.would_exceed_density(&[], 0.29));
+        assert!(!set.would_exceed_density(&[4, 5], 0.5));
+        assert!(set.would_exceed_density(&[4, 5, 6], 0.5));
+        // Already present keys don't add to the density
+        assert!(!set.would_exceed_density(&[1, 2, 4, 5], 0.5));
+        assert!(!set.would_exceed_density(&[4, 5, 10], 0.5));
+        assert!(!FastSet::new(0).unwrap().would_exceed_density(&[0], 0.0));
     }
 }